@@ -30,6 +30,23 @@ fn main() {
         let hash = hash.as_deref().unwrap_or("0000000");
 
         println!("cargo:rustc-env=GIT_HASH={}", hash);
+
+        // pulled updates must carry a higher one, so an old signed manifest cannot downgrade
+        let seq = std::env::var("BUILD_SEQ").ok().or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-list", "--count", "HEAD"])
+                .output()
+                .ok()?;
+            String::from_utf8(output.stdout)
+                .ok()
+                .map(|s| s.trim().to_string())
+        });
+        let seq = seq.as_deref().unwrap_or("0");
+        assert!(
+            seq.parse::<u32>().is_ok(),
+            "BUILD_SEQ must be a number, not {seq}"
+        );
+        println!("cargo:rustc-env=BUILD_SEQ={}", seq);
     }
 
     {
@@ -80,6 +97,119 @@ fn main() {
     let org = std::env::var("ORG").expect("ORG environment variable must be set");
     println!("cargo:rustc-env=ORG={}", org);
 
+    // the other GPIOs are taken by the SPI, UART, relay and LED pins
+    if let Ok(pin) = std::env::var("RS485_DE_PIN") {
+        assert!(
+            ["4", "12", "13", "15", "22"].contains(&pin.as_str()),
+            "RS485_DE_PIN must be one of 4, 12, 13, 15 or 22, not {pin}"
+        );
+    }
+
+    // the HTTP server has port 80
+    if let Ok(port) = std::env::var("TCP_PORT") {
+        assert!(
+            port.parse::<u16>()
+                .is_ok_and(|port| port != 0 && port != 80),
+            "TCP_PORT must be a port other than 0 or 80, not {port}"
+        );
+    }
+    // each client takes a socket, buffers and a task
+    if let Ok(clients) = std::env::var("TCP_CLIENTS") {
+        assert!(
+            clients.parse::<usize>().is_ok_and(|n| (1..=8).contains(&n)),
+            "TCP_CLIENTS must be 1 to 8, not {clients}"
+        );
+    }
+    // discovery and mDNS have 30303 and 5353
+    if let Ok(port) = std::env::var("UDP_PORT") {
+        assert!(
+            port.parse::<u16>()
+                .is_ok_and(|port| ![0, 5353, 30303].contains(&port)),
+            "UDP_PORT must be a port other than 0, 5353 or 30303, not {port}"
+        );
+    }
+    if let Ok(uart) = std::env::var("UDP_UART") {
+        assert!(
+            ["0", "1"].contains(&uart.as_str()),
+            "UDP_UART must be the UART port to bridge, 0 or 1, not {uart}"
+        );
+    }
+    if let Ok(framing) = std::env::var("TCP_FRAMING") {
+        let delimiter = framing
+            .strip_prefix("delim:")
+            .is_some_and(|byte| byte.parse::<u8>().is_ok());
+        assert!(
+            delimiter || ["newline", "crlf", "length"].contains(&framing.as_str()),
+            "TCP_FRAMING must be newline, crlf, length or delim:<0-255>, not {framing}"
+        );
+    }
+    if let Ok(oversize) = std::env::var("TCP_OVERSIZE") {
+        assert!(
+            ["split", "truncate", "drop"].contains(&oversize.as_str()),
+            "TCP_OVERSIZE must be split, truncate or drop, not {oversize}"
+        );
+    }
+
+    // typed in by whoever provisions the device over BLE, and kept to what a BLE passkey allows
+    if let Ok(key) = std::env::var("BLE_PASSKEY") {
+        assert!(
+            key.len() == 6 && key.chars().all(|c| c.is_ascii_digit()),
+            "BLE_PASSKEY must be six digits"
+        );
+    }
+
+    // pulled updates are only as trustworthy as the key their manifest is signed with
+    if std::env::var("OTA_URL").is_ok() {
+        let key = std::env::var("OTA_PUBKEY").expect("OTA_URL needs OTA_PUBKEY");
+        assert!(
+            [66, 130].contains(&key.len()) && key.chars().all(|c| c.is_ascii_hexdigit()),
+            "OTA_PUBKEY must be a SEC1 P-256 public key in hex"
+        );
+    }
+
+    // optional settings, read with option_env! by the firmware
+    for key in [
+        "TCP_PORT",
+        "TCP_CLIENTS",
+        "TCP_REMOTE",
+        "TCP_FRAMING",
+        "TCP_OVERSIZE",
+        "TCP_FLUSH_MS",
+        "UDP_PORT",
+        "UDP_UART",
+        "HOSTNAME",
+        "ETH_IP",
+        "ETH_GW",
+        "ETH_DNS",
+        "ETH_DHCP",
+        "WIFI_IP",
+        "WIFI_GW",
+        "WIFI_DNS",
+        "WIFI_DHCP",
+        "WIFI_NETWORKS",
+        "BLE_FILTER",
+        "BLE_INTERVAL",
+        "BLE_PASSKEY",
+        "OTA_URL",
+        "OTA_PUBKEY",
+        "OTA_INTERVAL",
+        "WEBHOOK_URL",
+        "WEBHOOK_SECRET",
+        "LOG_LEVEL",
+        "MEM_INTERVAL",
+        "MEM_TREND_WINDOW",
+        "RS485_DE_PIN",
+        "RS485_DE_ACTIVE",
+        "RS485_PRE_TX_US",
+        "RS485_POST_TX_US",
+        "RS485_ECHO",
+        "RS485_ECHO_TIMEOUT_MS",
+    ] {
+        if let Ok(value) = std::env::var(key) {
+            println!("cargo:rustc-env={}={}", key, value);
+        }
+    }
+
     {
         let mac = std::env::var("MAC").ok().unwrap_or_else(|| {
             let mac = generate_random_mac();
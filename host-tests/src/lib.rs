@@ -0,0 +1,42 @@
+#![no_std]
+// `Transport` is public here, while in the firmware binary the lint does not apply.
+#![allow(async_fn_in_trait)]
+
+// The firmware's `bus` module, flattened: transport.rs finds `Channel` in its parent.
+pub use channel::Channel;
+pub use transport::Transport;
+
+#[path = "../../src/bus/channel.rs"]
+mod channel;
+#[path = "../../src/bus/transport.rs"]
+mod transport;
+
+#[path = "../../src/uart/half_duplex.rs"]
+pub mod half_duplex;
+
+#[path = "../../src/tcp/framing.rs"]
+pub mod framing;
+
+#[path = "../../src/ble/decode.rs"]
+pub mod ble;
+
+extern crate alloc;
+
+/// The firmware keeps these buffers in PSRAM; here they live on the host's heap.
+pub type MyHeapVec<T> = alloc::vec::Vec<T>;
+
+#[macro_export]
+macro_rules! vec_in_myheap {
+    ($value:expr; $len:expr) => {
+        alloc::vec![$value; $len]
+    };
+}
+
+/// As in the firmware's main.rs, and likewise after the modules that import it. `ORG` and `ID`
+/// come from .cargo/config.toml.
+#[macro_export]
+macro_rules! iot_topic {
+    () => {
+        concat!("iot/", env!("ORG"), "/pcb/", env!("ID"))
+    };
+}
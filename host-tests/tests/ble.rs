@@ -0,0 +1,209 @@
+use host_tests::ble::{Reading, bthome, decode, ruuvi, structures, uuids};
+
+/// Flags, as every advertisement here starts with them.
+const FLAGS: [u8; 3] = [0x02, 0x01, 0x06];
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// `FLAGS` and one structure of `kind` holding `data`.
+fn advert(kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut adv = FLAGS.to_vec();
+    adv.push(data.len() as u8 + 1);
+    adv.push(kind);
+    adv.extend_from_slice(data);
+    adv
+}
+
+fn bthome_advert(objects: &[u8]) -> Vec<u8> {
+    let mut data = vec![0xd2, 0xfc];
+    data.extend_from_slice(objects);
+    advert(0x16, &data)
+}
+
+fn ruuvi_advert(raw: &str) -> Vec<u8> {
+    let mut data = vec![0x99, 0x04];
+    data.extend(hex(raw));
+    advert(0xff, &data)
+}
+
+fn close(value: Option<f32>, expected: f32) {
+    let value = value.unwrap_or_else(|| panic!("expected {expected}, got nothing"));
+    assert!((value - expected).abs() < 1e-3, "{value} != {expected}");
+}
+
+fn empty(r: &Reading) -> bool {
+    r.temperature.is_none()
+        && r.humidity.is_none()
+        && r.pressure.is_none()
+        && r.battery.is_none()
+        && r.voltage.is_none()
+        && r.illuminance.is_none()
+        && r.co2.is_none()
+        && r.moisture.is_none()
+        && r.acceleration.is_none()
+        && r.movement.is_none()
+        && r.button.is_none()
+        && r.sequence.is_none()
+}
+
+#[test]
+fn structures_stop_at_a_bad_length() {
+    let adv = [0x02, 0x01, 0x06, 0x03, 0x03, 0xd2, 0xfc];
+    assert_eq!(
+        structures(&adv).collect::<Vec<_>>(),
+        [(0x01, &[0x06][..]), (0x03, &[0xd2, 0xfc][..])]
+    );
+    // a length past the end, or of zero, ends the payload
+    assert_eq!(structures(&[0x02, 0x01, 0x06, 0x05, 0xff, 0x01]).count(), 1);
+    assert_eq!(structures(&[0x00, 0x02, 0x01, 0x06]).count(), 0);
+    assert_eq!(structures(&[]).count(), 0);
+}
+
+#[test]
+fn uuids_come_from_lists_and_service_data() {
+    let mut adv = advert(0x03, &[0x0f, 0x18, 0x1a, 0x18]);
+    adv.extend_from_slice(&[0x05, 0x16, 0xd2, 0xfc, 0x40, 0x01]);
+    assert_eq!(uuids(&adv).collect::<Vec<_>>(), [0x180f, 0x181a, 0xfcd2]);
+    // an odd byte out is not half a UUID
+    assert_eq!(uuids(&advert(0x02, &[0x0f, 0x18, 0x1a])).count(), 1);
+}
+
+/// The example in the BTHome v2 format description: 25.06 °C, 50.55 %.
+#[test]
+fn bthome_spec_example() {
+    let r = decode(&bthome_advert(&hex("4002ca0903bf13"))).unwrap();
+    assert_eq!(r.format, "bthome");
+    close(r.temperature, 25.06);
+    close(r.humidity, 50.55);
+}
+
+/// One of each object id the decoder knows, with the values from the BTHome object table.
+#[test]
+fn bthome_objects() {
+    let r = bthome(&hex(concat!(
+        "40",       // v2, unencrypted
+        "0009",     // packet id 9
+        "0161",     // battery 97 %
+        "04138a01", // pressure 1008.83 hPa
+        "05138a14", // illuminance 13460.67 lux
+        "0c020c",   // voltage 3.074 V
+        "12e204",   // CO2 1250 ppm
+        "14020c",   // moisture 30.74 %
+        "3a01",     // button press
+    )))
+    .unwrap();
+    assert_eq!(r.sequence, Some(9));
+    assert_eq!(r.battery, Some(97));
+    close(r.pressure, 1008.83);
+    close(r.illuminance, 13460.67);
+    close(r.voltage, 3.074);
+    assert_eq!(r.co2, Some(1250));
+    close(r.moisture, 30.74);
+    assert_eq!(r.button, Some(1));
+
+    let r = bthome(&hex("40451101" /* 27.3 °C */)).unwrap();
+    close(r.temperature, 27.3);
+    let r = bthome(&hex("4002d0fe2e232f10")).unwrap();
+    close(r.temperature, -3.04);
+    close(r.humidity, 35.0);
+    close(r.moisture, 16.0);
+}
+
+#[test]
+fn bthome_rejects_encrypted_and_other_versions() {
+    assert!(bthome(&hex("4102ca09")).is_none());
+    assert!(bthome(&hex("2002ca09")).is_none());
+    assert!(bthome(&[]).is_none());
+    // the service UUID with nothing after it is not a reading
+    assert!(decode(&bthome_advert(&[])).is_none());
+}
+
+#[test]
+fn bthome_stops_at_unknown_or_truncated_objects() {
+    // 0xf0 is not known, so where the humidity starts cannot be told
+    let r = bthome(&hex("4002ca09f00103bf13")).unwrap();
+    close(r.temperature, 25.06);
+    assert!(r.humidity.is_none());
+
+    let r = bthome(&hex("4002ca0903bf")).unwrap();
+    close(r.temperature, 25.06);
+    assert!(r.humidity.is_none());
+
+    assert!(empty(&bthome(&hex("4002ca")).unwrap()));
+    assert!(empty(&bthome(&hex("40")).unwrap()));
+}
+
+/// The valid example from the Ruuvi data format 5 specification.
+#[test]
+fn ruuvi_spec_example() {
+    let r = decode(&ruuvi_advert(
+        "0512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F",
+    ))
+    .unwrap();
+    assert_eq!(r.format, "ruuvi");
+    close(r.temperature, 24.3);
+    close(r.humidity, 53.49);
+    close(r.pressure, 1000.44);
+    assert_eq!(r.acceleration, Some([4, -4, 1036]));
+    close(r.voltage, 2.977);
+    assert_eq!(r.movement, Some(66));
+    assert_eq!(r.sequence, Some(205));
+}
+
+/// The maximum and minimum value examples from the specification.
+#[test]
+fn ruuvi_spec_limits() {
+    let r = ruuvi(&hex("057FFFFFFEFFFE7FFF7FFF7FFFFFDEFEFFFECBB8334C884F")).unwrap();
+    close(r.temperature, 163.835);
+    close(r.humidity, 163.835);
+    close(r.pressure, 1155.34);
+    assert_eq!(r.acceleration, Some([32767, 32767, 32767]));
+    close(r.voltage, 3.646);
+    assert_eq!(r.movement, Some(254));
+    assert_eq!(r.sequence, Some(65534));
+
+    let r = ruuvi(&hex("058001000000008001800180010000000000CBB8334C884F")).unwrap();
+    close(r.temperature, -163.835);
+    close(r.humidity, 0.0);
+    close(r.pressure, 500.0);
+    assert_eq!(r.acceleration, Some([-32767, -32767, -32767]));
+    close(r.voltage, 1.6);
+    assert_eq!(r.movement, Some(0));
+    assert_eq!(r.sequence, Some(0));
+}
+
+/// The invalid values example: every field at "not available" is left out.
+#[test]
+fn ruuvi_spec_invalid_values() {
+    let r = ruuvi(&hex("058000FFFFFFFF800080008000FFFFFFFFFFFFFFFFFFFFFF")).unwrap();
+    assert!(empty(&r));
+}
+
+#[test]
+fn ruuvi_rejects_truncated_and_other_formats() {
+    let raw = "0512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F";
+    assert!(ruuvi(&hex(&raw[..raw.len() - 2])).is_none());
+    assert!(ruuvi(&[]).is_none());
+    assert!(ruuvi(&hex(&format!("03{}", &raw[2..]))).is_none());
+    // the right payload under another company id
+    let mut data = vec![0x59, 0x00];
+    data.extend(hex(raw));
+    assert!(decode(&advert(0xff, &data)).is_none());
+    // a structure that claims more bytes than the advertisement has
+    let mut adv = ruuvi_advert(raw);
+    adv.pop();
+    assert!(decode(&adv).is_none());
+}
+
+#[test]
+fn decode_skips_other_structures() {
+    let mut adv = advert(0x09, b"sensor");
+    adv.extend_from_slice(&bthome_advert(&hex("4002ca09"))[FLAGS.len()..]);
+    close(decode(&adv).unwrap().temperature, 25.06);
+    assert!(decode(&FLAGS).is_none());
+}
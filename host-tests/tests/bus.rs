@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+
+use futures_executor::block_on;
+use host_tests::{Channel, Transport, iot_topic};
+
+/// A transport that keeps what it is given, up to `capacity` messages.
+struct Memory {
+    configured: bool,
+    capacity: usize,
+    messages: RefCell<Vec<(String, Vec<u8>)>>,
+}
+
+impl Memory {
+    fn new(capacity: usize) -> Self {
+        Self {
+            configured: true,
+            capacity,
+            messages: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn unconfigured() -> Self {
+        Self {
+            configured: false,
+            ..Self::new(8)
+        }
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.messages
+            .borrow()
+            .iter()
+            .map(|(t, _)| t.clone())
+            .collect()
+    }
+
+    fn push(&self, channel: Channel, payload: &[u8]) {
+        self.messages
+            .borrow_mut()
+            .push((channel.topic().to_string(), payload.to_vec()));
+    }
+}
+
+impl Transport for Memory {
+    async fn send(&self, channel: Channel, payload: &[u8]) {
+        if self.configured {
+            self.push(channel, payload);
+        }
+    }
+
+    fn try_send(&self, channel: Channel, payload: &[u8]) -> bool {
+        if !self.configured || self.messages.borrow().len() == self.capacity {
+            return false;
+        }
+        self.push(channel, payload);
+        true
+    }
+}
+
+#[test]
+fn topics_are_under_the_device_prefix() {
+    let cases = [
+        (Channel::Logs, concat!(iot_topic!(), "/logs")),
+        (Channel::Data, concat!(iot_topic!(), "/data")),
+        (Channel::DataSlot(2), concat!(iot_topic!(), "/data/2")),
+        (Channel::Uart(1), concat!(iot_topic!(), "/uart/1")),
+        (
+            Channel::UartReply(0),
+            concat!(iot_topic!(), "/uart/0/reply"),
+        ),
+        (
+            Channel::Ble([0xc4, 0x7c, 0x8d, 0x6a, 0x01, 0xfe]),
+            concat!(iot_topic!(), "/ble/c47c8d6a01fe"),
+        ),
+        (Channel::Memory, concat!(iot_topic!(), "/memory")),
+    ];
+    for (channel, topic) in cases {
+        assert_eq!(channel.topic().as_str(), topic);
+    }
+}
+
+#[test]
+fn echo_is_outside_the_device_prefix() {
+    assert_eq!(Channel::Echo.topic().as_str(), "/echo");
+}
+
+#[test]
+fn longest_topic_fits() {
+    Channel::UartReply(usize::MAX).topic();
+    Channel::DataSlot(usize::MAX).topic();
+}
+
+#[test]
+fn send_reaches_every_configured_transport() {
+    let transport = (Memory::new(8), Memory::new(8));
+    block_on(transport.send(Channel::Udp, b"hello"));
+    for memory in [&transport.0, &transport.1] {
+        assert_eq!(
+            *memory.messages.borrow(),
+            [(concat!(iot_topic!(), "/udp").to_string(), b"hello".to_vec())]
+        );
+    }
+}
+
+#[test]
+fn unconfigured_transport_takes_nothing() {
+    let transport = (Memory::unconfigured(), Memory::new(8));
+    block_on(transport.send(Channel::Link, b"eth"));
+    assert!(transport.try_send(Channel::Link, b"wifi"));
+    assert!(transport.0.messages.borrow().is_empty());
+    assert_eq!(transport.1.topics().len(), 2);
+}
+
+#[test]
+fn try_send_is_queued_if_any_transport_took_it() {
+    let transport = (Memory::new(0), Memory::new(1));
+    assert!(transport.try_send(Channel::Crash, b"{}"));
+    assert!(!transport.try_send(Channel::Crash, b"{}"));
+    assert_eq!(transport.1.topics(), [concat!(iot_topic!(), "/crash")]);
+}
+
+#[test]
+fn try_send_offers_to_both_even_when_the_first_takes_it() {
+    let transport = (Memory::new(8), Memory::new(8));
+    assert!(transport.try_send(Channel::OtaLog, b"downloaded"));
+    assert_eq!(transport.0.topics(), transport.1.topics());
+}
+
+#[test]
+fn transports_nest() {
+    let transport = (Memory::unconfigured(), (Memory::new(8), Memory::new(8)));
+    block_on(transport.send(Channel::WifiScan, b"[]"));
+    assert_eq!(
+        transport.1.0.topics(),
+        [concat!(iot_topic!(), "/wifi/scan")]
+    );
+    assert_eq!(transport.1.1.topics().len(), 1);
+}
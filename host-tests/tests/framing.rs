@@ -0,0 +1,182 @@
+use host_tests::framing::{Event, Framer, Framing, Oversize};
+
+const CAPACITY: usize = 4;
+
+/// Feeds `data` in reads of `chunk` bytes, like the socket loop does, and returns every event
+/// with the frame it made ready.
+fn feed(framer: &mut Framer, data: &[u8], chunk: usize) -> Vec<(Event, Vec<u8>)> {
+    let mut events = Vec::new();
+    for mut read in data.chunks(chunk) {
+        while !read.is_empty() {
+            let (used, event) = framer.push(read);
+            read = &read[used..];
+            if let Some(event) = event {
+                events.push((event, framer.frame().to_vec()));
+                framer.clear();
+            }
+        }
+    }
+    events
+}
+
+fn frames(framing: Framing, oversize: Oversize, data: &[u8]) -> Vec<(Event, Vec<u8>)> {
+    let whole = feed(
+        &mut Framer::new(framing, oversize, CAPACITY),
+        data,
+        data.len(),
+    );
+    // splitting the reads anywhere must not change the outcome
+    for chunk in 1..data.len() {
+        let split = feed(&mut Framer::new(framing, oversize, CAPACITY), data, chunk);
+        assert_eq!(split, whole, "reads of {chunk} bytes");
+    }
+    whole
+}
+
+fn frame(event: Event, bytes: &[u8]) -> (Event, Vec<u8>) {
+    (event, bytes.to_vec())
+}
+
+#[test]
+fn newline_keeps_the_delimiter() {
+    assert_eq!(
+        frames(Framing::Newline, Oversize::Split, b"ab\ncd\n"),
+        [frame(Event::Frame, b"ab\n"), frame(Event::Frame, b"cd\n")]
+    );
+}
+
+#[test]
+fn crlf_needs_both_bytes() {
+    assert_eq!(
+        frames(Framing::CrLf, Oversize::Split, b"a\nb\r\n"),
+        [frame(Event::Split, b"a\nb\r"), frame(Event::Frame, b"\n")]
+    );
+    assert_eq!(
+        frames(Framing::CrLf, Oversize::Split, b"a\r\n\r\n"),
+        [frame(Event::Frame, b"a\r\n"), frame(Event::Frame, b"\r\n")]
+    );
+}
+
+#[test]
+fn custom_delimiter() {
+    assert_eq!(
+        frames(Framing::Delimiter(0), Oversize::Split, b"x\0yz\0"),
+        [frame(Event::Frame, b"x\0"), frame(Event::Frame, b"yz\0")]
+    );
+}
+
+#[test]
+fn length_prefix_strips_the_header() {
+    assert_eq!(
+        frames(
+            Framing::LengthPrefix,
+            Oversize::Split,
+            b"\0\x02hi\0\0\0\x01z"
+        ),
+        [frame(Event::Frame, b"hi"), frame(Event::Frame, b"z")]
+    );
+}
+
+#[test]
+fn delimited_oversize_split() {
+    assert_eq!(
+        frames(Framing::Newline, Oversize::Split, b"abcdef\ngh\n"),
+        [
+            frame(Event::Split, b"abcd"),
+            frame(Event::Frame, b"ef\n"),
+            frame(Event::Frame, b"gh\n"),
+        ]
+    );
+}
+
+#[test]
+fn delimited_oversize_truncate() {
+    assert_eq!(
+        frames(Framing::Newline, Oversize::Truncate, b"abcdef\ngh\n"),
+        [
+            frame(Event::Truncated, b"abcd"),
+            frame(Event::Frame, b"gh\n")
+        ]
+    );
+    // the delimiter is the byte that does not fit, so nothing is left to skip
+    assert_eq!(
+        frames(Framing::Newline, Oversize::Truncate, b"abcd\nx\n"),
+        [
+            frame(Event::Truncated, b"abcd"),
+            frame(Event::Frame, b"x\n")
+        ]
+    );
+}
+
+#[test]
+fn delimited_oversize_drop() {
+    assert_eq!(
+        frames(Framing::Newline, Oversize::Drop, b"abcdef\ngh\n"),
+        [frame(Event::Dropped, b""), frame(Event::Frame, b"gh\n")]
+    );
+}
+
+#[test]
+fn length_prefix_oversize_split() {
+    assert_eq!(
+        frames(
+            Framing::LengthPrefix,
+            Oversize::Split,
+            b"\0\x06abcdef\0\x01z"
+        ),
+        [
+            frame(Event::Split, b"abcd"),
+            frame(Event::Frame, b"ef"),
+            frame(Event::Frame, b"z"),
+        ]
+    );
+}
+
+#[test]
+fn length_prefix_oversize_truncate() {
+    assert_eq!(
+        frames(
+            Framing::LengthPrefix,
+            Oversize::Truncate,
+            b"\0\x06abcdef\0\x01z"
+        ),
+        [frame(Event::Truncated, b"abcd"), frame(Event::Frame, b"z")]
+    );
+}
+
+#[test]
+fn length_prefix_oversize_drop() {
+    assert_eq!(
+        frames(
+            Framing::LengthPrefix,
+            Oversize::Drop,
+            b"\0\x06abcdef\0\x01z"
+        ),
+        [frame(Event::Dropped, b""), frame(Event::Frame, b"z")]
+    );
+}
+
+#[test]
+fn flush_hands_out_a_partial_delimited_frame() {
+    let mut framer = Framer::new(Framing::Newline, Oversize::Split, CAPACITY);
+    assert!(feed(&mut framer, b"ab", 2).is_empty());
+    assert!(framer.flushable());
+    assert_eq!(framer.flush(), Some(Event::Frame));
+    assert_eq!(framer.frame(), b"ab");
+    framer.clear();
+    assert!(!framer.flushable());
+    assert_eq!(framer.flush(), None);
+}
+
+#[test]
+fn flush_leaves_a_length_prefixed_frame_alone() {
+    let mut framer = Framer::new(Framing::LengthPrefix, Oversize::Split, CAPACITY);
+    assert!(feed(&mut framer, b"\0\x04ab", 4).is_empty());
+    assert!(!framer.flushable());
+    assert_eq!(framer.flush(), None);
+    // the rest still belongs to the same frame, not to a new header
+    assert_eq!(
+        feed(&mut framer, b"cd\0\x01z", 1),
+        [frame(Event::Frame, b"abcd"), frame(Event::Frame, b"z")]
+    );
+}
@@ -0,0 +1,287 @@
+use std::{cell::RefCell, collections::VecDeque, convert::Infallible, rc::Rc};
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use futures_executor::block_on;
+use host_tests::half_duplex::{EchoMatcher, Rs485Config, Rs485Error, transmit};
+
+/// How long one byte takes on the wire in the simulation.
+const CHAR_TIME: Duration = Duration::from_micros(200);
+
+/// A half-duplex transceiver on a shared bus. Bytes written go to a shift register and only
+/// reach the line while DE is asserted; with the receiver left enabled they come back as echo.
+#[derive(Default)]
+struct Bus {
+    de_active_high: bool,
+    /// Current level of the DE pin.
+    de_level: bool,
+    /// When DE was last asserted and released.
+    de_on: Option<Instant>,
+    de_off: Option<Instant>,
+    first_write: Option<Instant>,
+    flushed: Option<Instant>,
+    /// Bytes in the shift register, not yet on the line.
+    in_flight: usize,
+    /// DE was released with bytes still in the shift register.
+    cut: bool,
+    /// Bytes written while DE was not asserted, lost.
+    undriven: usize,
+    /// The receiver hears our own transmission.
+    echo: bool,
+    /// Another node drives the line at this offset of our frame, garbling the echo.
+    collide_at: Option<usize>,
+    /// What another node sends right after our frame.
+    reply: Vec<u8>,
+    fail_write: bool,
+    rx: VecDeque<u8>,
+    sent: usize,
+}
+
+impl Bus {
+    fn new(config: &Rs485Config) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            de_active_high: config.de_active_high,
+            de_level: !config.de_active_high,
+            echo: true,
+            ..Default::default()
+        }))
+    }
+
+    fn driving(&self) -> bool {
+        self.de_level == self.de_active_high
+    }
+}
+
+struct Port(Rc<RefCell<Bus>>);
+
+impl ErrorType for Port {
+    type Error = ErrorKind;
+}
+
+impl Write for Port {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+        let mut bus = self.0.borrow_mut();
+        if bus.fail_write {
+            return Err(ErrorKind::Other);
+        }
+        bus.first_write.get_or_insert(Instant::now());
+        if !bus.driving() {
+            bus.undriven += buf.len();
+            return Ok(buf.len());
+        }
+        for &b in buf {
+            let b = match bus.collide_at {
+                Some(at) if at == bus.sent => !b,
+                _ => b,
+            };
+            if bus.echo {
+                bus.rx.push_back(b);
+            }
+            bus.sent += 1;
+        }
+        bus.in_flight += buf.len();
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), ErrorKind> {
+        let in_flight = self.0.borrow().in_flight;
+        Timer::after(CHAR_TIME * in_flight as u32).await;
+        let mut bus = self.0.borrow_mut();
+        bus.in_flight = 0;
+        bus.flushed = Some(Instant::now());
+        let reply = core::mem::take(&mut bus.reply);
+        bus.rx.extend(reply);
+        Ok(())
+    }
+}
+
+impl Read for Port {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        if self.0.borrow().rx.is_empty() {
+            // nothing on the line, ever
+            core::future::pending::<()>().await;
+        }
+        let mut bus = self.0.borrow_mut();
+        let n = buf.len().min(bus.rx.len());
+        for (slot, b) in buf.iter_mut().zip(bus.rx.drain(..n)) {
+            *slot = b;
+        }
+        Ok(n)
+    }
+}
+
+struct De(Rc<RefCell<Bus>>);
+
+impl embedded_hal::digital::ErrorType for De {
+    type Error = Infallible;
+}
+
+impl embedded_hal::digital::OutputPin for De {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.set(true);
+        Ok(())
+    }
+}
+
+impl De {
+    fn set(&mut self, level: bool) {
+        let mut bus = self.0.borrow_mut();
+        let was_driving = bus.driving();
+        bus.de_level = level;
+        match (was_driving, bus.driving()) {
+            (false, true) => bus.de_on = Some(Instant::now()),
+            (true, false) => {
+                bus.de_off = Some(Instant::now());
+                bus.cut |= bus.in_flight > 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn config() -> Rs485Config {
+    Rs485Config {
+        pre_tx: Duration::from_micros(500),
+        post_tx: Duration::from_micros(500),
+        de_active_high: true,
+        suppress_echo: false,
+        echo_timeout: Duration::from_millis(20),
+    }
+}
+
+fn run(
+    bus: &Rc<RefCell<Bus>>,
+    config: &Rs485Config,
+    frame: &[u8],
+    rx: &mut [u8],
+) -> Result<usize, Rs485Error<ErrorKind>> {
+    let mut port = Port(bus.clone());
+    let mut de = De(bus.clone());
+    block_on(transmit(&mut port, &mut de, config, frame, rx))
+}
+
+#[test]
+fn de_is_held_until_the_last_byte_is_out() {
+    let config = config();
+    let bus = Bus::new(&config);
+    let frame = [0x01, 0x03, 0x00, 0x00, 0x00, 0x02, 0xc4, 0x0b];
+    assert_eq!(run(&bus, &config, &frame, &mut [0; 64]), Ok(0));
+
+    let bus = bus.borrow();
+    assert!(!bus.cut, "DE dropped with bytes in the shift register");
+    assert_eq!(bus.undriven, 0);
+    assert!(!bus.driving());
+    let (de_on, de_off) = (bus.de_on.unwrap(), bus.de_off.unwrap());
+    assert!(bus.first_write.unwrap() - de_on >= config.pre_tx);
+    assert!(de_off - bus.flushed.unwrap() >= config.post_tx);
+    assert!(de_off - de_on >= CHAR_TIME * frame.len() as u32);
+}
+
+#[test]
+fn inverted_de_is_driven_low_to_transmit() {
+    let config = Rs485Config {
+        de_active_high: false,
+        ..config()
+    };
+    let bus = Bus::new(&config);
+    assert_eq!(run(&bus, &config, b"hello", &mut [0; 64]), Ok(0));
+
+    let bus = bus.borrow();
+    assert_eq!(bus.undriven, 0);
+    assert!(!bus.cut);
+    assert!(bus.de_level, "DE left asserted after the frame");
+}
+
+#[test]
+fn de_is_released_when_the_write_fails() {
+    let config = config();
+    let bus = Bus::new(&config);
+    bus.borrow_mut().fail_write = true;
+    assert_eq!(
+        run(&bus, &config, b"hello", &mut [0; 64]),
+        Err(Rs485Error::Write(ErrorKind::Other))
+    );
+    assert!(!bus.borrow().driving());
+}
+
+#[test]
+fn echo_is_left_alone_unless_suppressed() {
+    let config = config();
+    let bus = Bus::new(&config);
+    assert_eq!(run(&bus, &config, b"hello", &mut [0; 64]), Ok(0));
+    assert_eq!(bus.borrow().rx.len(), 5);
+}
+
+#[test]
+fn echo_is_consumed() {
+    let config = Rs485Config {
+        suppress_echo: true,
+        ..config()
+    };
+    let bus = Bus::new(&config);
+    assert_eq!(run(&bus, &config, b"hello", &mut [0; 64]), Ok(0));
+    assert!(bus.borrow().rx.is_empty());
+}
+
+#[test]
+fn reply_after_the_echo_is_kept() {
+    let config = Rs485Config {
+        suppress_echo: true,
+        ..config()
+    };
+    let bus = Bus::new(&config);
+    bus.borrow_mut().reply = b"world".to_vec();
+    let mut rx = [0; 64];
+    assert_eq!(run(&bus, &config, b"hello", &mut rx), Ok(5));
+    assert_eq!(&rx[..5], b"world");
+}
+
+#[test]
+fn garbled_echo_is_a_collision() {
+    let config = Rs485Config {
+        suppress_echo: true,
+        ..config()
+    };
+    let bus = Bus::new(&config);
+    bus.borrow_mut().collide_at = Some(3);
+    assert_eq!(
+        run(&bus, &config, b"hello", &mut [0; 64]),
+        Err(Rs485Error::Collision(3))
+    );
+}
+
+#[test]
+fn missing_echo_times_out() {
+    let config = Rs485Config {
+        suppress_echo: true,
+        ..config()
+    };
+    let bus = Bus::new(&config);
+    bus.borrow_mut().echo = false;
+    assert_eq!(
+        run(&bus, &config, b"hello", &mut [0; 64]),
+        Err(Rs485Error::EchoLost(0))
+    );
+}
+
+#[test]
+fn echo_split_across_reads() {
+    let mut echo = EchoMatcher::new(b"hello");
+    assert_eq!(echo.feed::<ErrorKind>(b"he"), Ok(2));
+    assert!(!echo.done());
+    assert_eq!(echo.feed::<ErrorKind>(b"lloXY"), Ok(3));
+    assert!(echo.done());
+    assert_eq!(echo.matched(), 5);
+}
+
+#[test]
+fn collision_offset_counts_earlier_reads() {
+    let mut echo = EchoMatcher::new(b"hello");
+    assert_eq!(echo.feed::<ErrorKind>(b"hel"), Ok(3));
+    assert_eq!(echo.feed::<ErrorKind>(b"lX"), Err(Rs485Error::Collision(4)));
+}
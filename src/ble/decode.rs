@@ -0,0 +1,168 @@
+// Pure byte parsing, so `host-tests` can run the decoders on captured advertisements. Keep
+// trouble-host and defmt out of this file.
+
+use serde::Serialize;
+
+const AD_UUID16_INCOMPLETE: u8 = 0x02;
+const AD_UUID16_COMPLETE: u8 = 0x03;
+const AD_SERVICE_DATA16: u8 = 0x16;
+const AD_MANUFACTURER: u8 = 0xff;
+const BTHOME_UUID: u16 = 0xfcd2;
+const RUUVI_COMPANY: u16 = 0x0499;
+const RUUVI_RAWV2: u8 = 0x05;
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Reading {
+    pub format: &'static str,
+    pub rssi: i8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f32>,
+    /// hPa
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<f32>,
+    /// %
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery: Option<u8>,
+    /// V
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voltage: Option<f32>,
+    /// lux
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub illuminance: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub co2: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moisture: Option<f32>,
+    /// mG, x/y/z
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acceleration: Option<[i16; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub movement: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub button: Option<u8>,
+    /// Packet id or measurement sequence, to spot duplicates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u16>,
+}
+
+/// Iterates the `(type, data)` structures of an advertising payload.
+pub fn structures(mut adv: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    core::iter::from_fn(move || {
+        let len = *adv.first()? as usize;
+        if len == 0 || adv.len() < len + 1 {
+            return None;
+        }
+        let (ad, rest) = adv[1..].split_at(len);
+        adv = rest;
+        Some((ad[0], &ad[1..]))
+    })
+}
+
+/// 16-bit service UUIDs the payload lists or carries service data for.
+pub fn uuids(adv: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    structures(adv).flat_map(|(kind, data)| {
+        let data = match kind {
+            AD_UUID16_INCOMPLETE | AD_UUID16_COMPLETE => data,
+            AD_SERVICE_DATA16 => &data[..data.len().min(2)],
+            _ => &[],
+        };
+        data.chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+    })
+}
+
+/// The first reading found in an advertising payload, with `rssi` left at 0.
+pub fn decode(adv: &[u8]) -> Option<Reading> {
+    structures(adv).find_map(|(kind, data)| match kind {
+        AD_SERVICE_DATA16
+            if data.len() > 2 && u16::from_le_bytes([data[0], data[1]]) == BTHOME_UUID =>
+        {
+            bthome(&data[2..])
+        }
+        AD_MANUFACTURER
+            if data.len() > 2 && u16::from_le_bytes([data[0], data[1]]) == RUUVI_COMPANY =>
+        {
+            ruuvi(&data[2..])
+        }
+        _ => None,
+    })
+}
+
+fn le(data: &[u8]) -> u32 {
+    data.iter().rev().fold(0, |acc, &b| acc << 8 | b as u32)
+}
+
+/// BTHome v2 service data, unencrypted. Stops at the first object id it does not know, as the
+/// length of what follows is then unknown.
+pub fn bthome(data: &[u8]) -> Option<Reading> {
+    let (&info, mut objects) = data.split_first()?;
+    if info & 0x01 != 0 || info >> 5 != 2 {
+        return None; // encrypted, or not version 2
+    }
+    let mut r = Reading {
+        format: "bthome",
+        ..Default::default()
+    };
+    while let Some((&id, rest)) = objects.split_first() {
+        let len = match id {
+            0x00 | 0x01 | 0x0f | 0x10 | 0x2e | 0x2f | 0x3a => 1,
+            0x02 | 0x03 | 0x0c | 0x12 | 0x14 | 0x45 => 2,
+            0x04 | 0x05 => 3,
+            _ => break,
+        };
+        if rest.len() < len {
+            break;
+        }
+        let raw = le(&rest[..len]);
+        match id {
+            0x00 => r.sequence = Some(raw as u16),
+            0x01 => r.battery = Some(raw as u8),
+            0x02 => r.temperature = Some(raw as u16 as i16 as f32 * 0.01),
+            0x03 => r.humidity = Some(raw as f32 * 0.01),
+            0x04 => r.pressure = Some(raw as f32 * 0.01),
+            0x05 => r.illuminance = Some(raw as f32 * 0.01),
+            0x0c => r.voltage = Some(raw as f32 * 0.001),
+            0x12 => r.co2 = Some(raw as u16),
+            0x14 => r.moisture = Some(raw as f32 * 0.01),
+            0x2e => r.humidity = Some(raw as f32),
+            0x2f => r.moisture = Some(raw as f32),
+            0x3a => r.button = Some(raw as u8),
+            0x45 => r.temperature = Some(raw as u16 as i16 as f32 * 0.1),
+            _ => {}
+        }
+        objects = &rest[len..];
+    }
+    Some(r)
+}
+
+/// Ruuvi data format 5 (RAWv2). Fields at their "not available" value are left out.
+pub fn ruuvi(data: &[u8]) -> Option<Reading> {
+    if data.len() < 24 || data[0] != RUUVI_RAWV2 {
+        return None;
+    }
+    let i16_at = |i: usize| i16::from_be_bytes([data[i], data[i + 1]]);
+    let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+    let power = u16_at(13);
+    Some(Reading {
+        format: "ruuvi",
+        temperature: Some(i16_at(1))
+            .filter(|&t| t != i16::MIN)
+            .map(|t| t as f32 * 0.005),
+        humidity: Some(u16_at(3))
+            .filter(|&h| h != u16::MAX)
+            .map(|h| h as f32 * 0.0025),
+        pressure: Some(u16_at(5))
+            .filter(|&p| p != u16::MAX)
+            .map(|p| (p as f32 + 50_000.0) / 100.0),
+        acceleration: Some([i16_at(7), i16_at(9), i16_at(11)])
+            .filter(|a| a.iter().all(|&v| v != i16::MIN)),
+        voltage: Some(power >> 5)
+            .filter(|&v| v != 0x7ff)
+            .map(|v| (v as f32 + 1600.0) / 1000.0),
+        movement: Some(data[15]).filter(|&m| m != u8::MAX),
+        sequence: Some(u16_at(16)).filter(|&s| s != u16::MAX),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,267 @@
+use alloc::format;
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use heapless09::Vec;
+use serde::Serialize;
+use trouble_host::prelude::*;
+
+use crate::{ethernet::MAC_ADDR, link, output, settings};
+
+const INFO_LEN: usize = 160;
+const STATUS_LEN: usize = 128;
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+/// Grace period for the write response to go out before a restart.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+#[gatt_server]
+struct Server {
+    device: DeviceService,
+}
+
+#[gatt_service(uuid = "5f1c0001-3a6e-4d2b-9a7e-8c4b2d6f1e00")]
+struct DeviceService {
+    /// `{"id","org","mac","git"}`.
+    #[characteristic(uuid = "5f1c0002-3a6e-4d2b-9a7e-8c4b2d6f1e00", read)]
+    info: Vec<u8, INFO_LEN>,
+    /// `{"link","ip","uptime"}`, notified every `STATUS_INTERVAL`.
+    #[characteristic(uuid = "5f1c0003-3a6e-4d2b-9a7e-8c4b2d6f1e00", read, notify)]
+    status: Vec<u8, STATUS_LEN>,
+    /// `{"ssid","password"}`; stored, then the device restarts.
+    #[characteristic(uuid = "5f1c0005-3a6e-4d2b-9a7e-8c4b2d6f1e00", write)]
+    wifi: Vec<u8, 128>,
+    /// `{"host","port"}`; stored, then the device restarts.
+    #[characteristic(uuid = "5f1c0006-3a6e-4d2b-9a7e-8c4b2d6f1e00", write)]
+    broker: Vec<u8, 96>,
+    /// One byte per relay, as for `/ctrl`: 0 off, 255 on, else seconds on.
+    #[characteristic(uuid = "5f1c0007-3a6e-4d2b-9a7e-8c4b2d6f1e00", write)]
+    relay: Vec<u8, { output::NUM_OUT }>,
+}
+
+#[derive(Serialize)]
+struct InfoPacket<'a> {
+    id: &'a str,
+    org: &'a str,
+    mac: &'a str,
+    git: &'a str,
+}
+
+#[derive(Serialize)]
+struct StatusPacket<'a> {
+    link: &'a str,
+    ip: &'a str,
+    uptime: u64,
+}
+
+fn json<const N: usize>(value: &impl Serialize) -> Vec<u8, N> {
+    let mut buf = [0u8; N];
+    let len = serde_json_core::to_slice(value, &mut buf).unwrap_or(0);
+    Vec::from_slice(&buf[..len]).unwrap_or_default()
+}
+
+fn info() -> Vec<u8, INFO_LEN> {
+    let m = MAC_ADDR;
+    let mac = format!(
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        m[0], m[1], m[2], m[3], m[4], m[5]
+    );
+    json(&InfoPacket {
+        id: env!("ID"),
+        org: env!("ORG"),
+        mac: &mac,
+        git: env!("GIT_HASH"),
+    })
+}
+
+fn status() -> Vec<u8, STATUS_LEN> {
+    let uplink = link::active();
+    let ip = uplink
+        .and_then(|u| u.stack.config_v4())
+        .map(|c| format!("{}", c.address.address()))
+        .unwrap_or_default();
+    json(&StatusPacket {
+        link: uplink.map(|u| u.link.as_str()).unwrap_or("none"),
+        ip: &ip,
+        uptime: Instant::now().as_secs(),
+    })
+}
+
+/// Advertises, then serves one connection at a time.
+pub(super) async fn serve<C: Controller>(peripheral: &mut Peripheral<'_, C, DefaultPacketPool>) {
+    let server = match Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
+        name: env!("ID"),
+        appearance: &appearance::power_device::GENERIC_POWER_DEVICE,
+    })) {
+        Ok(server) => server,
+        Err(e) => {
+            crate::log_warn!("gatt server {:?}", e);
+            return;
+        }
+    };
+    server.set(&server.device.info, &info()).ok();
+
+    loop {
+        let conn = match advertise(peripheral, &server).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                crate::log_warn!("ble advertise {:?}", e);
+                Timer::after_secs(1).await;
+                continue;
+            }
+        };
+        crate::log_info!("ble connected");
+        let restart = select(events(&server, &conn), notify_status(&server, &conn)).await;
+        if let Either::First(true) = restart {
+            Timer::after(RESTART_DELAY).await;
+            esp_hal::system::software_reset();
+        }
+    }
+}
+
+async fn advertise<'values, 'server, C: Controller>(
+    peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
+    server: &'server Server<'values>,
+) -> Result<GattConnection<'values, 'server, DefaultPacketPool>, BleHostError<C::Error>> {
+    let name = env!("ID").as_bytes();
+    let mut adv_data = [0; 31];
+    let len = AdStructure::encode_slice(
+        &[
+            AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+            AdStructure::CompleteLocalName(&name[..name.len().min(26)]),
+        ],
+        &mut adv_data[..],
+    )?;
+    let advertiser = peripheral
+        .advertise(
+            &Default::default(),
+            Advertisement::ConnectableScannableUndirected {
+                adv_data: &adv_data[..len],
+                scan_data: &[],
+            },
+        )
+        .await?;
+    Ok(advertiser.accept().await?.with_attribute_server(server)?)
+}
+
+/// The passkey printed on the device, checked by build.rs to be six digits.
+fn passkey() -> Option<u32> {
+    option_env!("BLE_PASSKEY").and_then(|key| key.parse().ok())
+}
+
+/// The device knows its passkey rather than showing one, so it takes the part of a keyboard and
+/// enters `BLE_PASSKEY` itself; the peer enters the same key. Without one it only pairs "Just
+/// Works", which never unlocks writes.
+pub(super) fn io_capabilities() -> IoCapabilities {
+    match passkey() {
+        Some(_) => IoCapabilities::KeyboardOnly,
+        None => IoCapabilities::NoInputNoOutput,
+    }
+}
+
+/// The link is encrypted with keys from passkey pairing. "Just Works" pairing encrypts too, but
+/// anyone in range could have done it.
+fn authenticated(conn: &GattConnection<'_, '_, DefaultPacketPool>) -> bool {
+    matches!(
+        conn.raw().security_level(),
+        Ok(SecurityLevel::EncryptedAuthenticated)
+    )
+}
+
+/// Handles GATT requests until the peer disconnects. Writes are refused until the peer has
+/// paired with `BLE_PASSKEY`; phones take the refusal as the cue to pair.
+/// Returns whether new settings were stored and the device should restart.
+async fn events(server: &Server<'_>, conn: &GattConnection<'_, '_, DefaultPacketPool>) -> bool {
+    let device = &server.device;
+    let mut restart = false;
+    loop {
+        let event = match conn.next().await {
+            GattConnectionEvent::Disconnected { reason } => {
+                crate::log_info!("ble disconnected: {:?}", reason);
+                return restart;
+            }
+            GattConnectionEvent::PassKeyInput => {
+                let entered = match passkey() {
+                    Some(key) => conn.raw().pass_key_input(key),
+                    None => conn.raw().pass_key_cancel(),
+                };
+                if let Err(e) = entered {
+                    crate::log_warn!("ble pairing {:?}", e);
+                }
+                continue;
+            }
+            GattConnectionEvent::PairingFailed(e) => {
+                crate::log_warn!("ble pairing failed: {:?}", e);
+                continue;
+            }
+            GattConnectionEvent::Gatt { event } => event,
+            _ => continue,
+        };
+
+        let result = match &event {
+            GattEvent::Read(read) if read.handle() == device.status.handle => {
+                server.set(&device.status, &status()).ok();
+                Ok(())
+            }
+            GattEvent::Write(_) if !authenticated(conn) => {
+                Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION)
+            }
+            GattEvent::Write(write) => {
+                let handle = write.handle();
+                if handle == device.wifi.handle {
+                    store(write.data(), |s, wifi| s.wifi = Some(wifi), &mut restart)
+                } else if handle == device.broker.handle {
+                    store(
+                        write.data(),
+                        |s, broker| s.broker = Some(broker),
+                        &mut restart,
+                    )
+                } else if handle == device.relay.handle {
+                    let mut relays = [None; output::NUM_OUT];
+                    for (relay, &value) in relays.iter_mut().zip(write.data()) {
+                        *relay = Some(value);
+                    }
+                    output::output_state(relays).await;
+                    Ok(())
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        };
+
+        let reply = match result {
+            Ok(()) => event.accept(),
+            Err(code) => event.reject(code),
+        };
+        match reply {
+            Ok(reply) => reply.send().await,
+            Err(e) => crate::log_warn!("gatt reply {:?}", e),
+        }
+    }
+}
+
+/// Parses a JSON write and persists it with `apply`.
+fn store<T: serde::de::DeserializeOwned>(
+    data: &[u8],
+    apply: impl FnOnce(&mut settings::Settings, T),
+    restart: &mut bool,
+) -> Result<(), AttErrorCode> {
+    let (value, _) =
+        serde_json_core::from_slice::<T>(data).map_err(|_| AttErrorCode::VALUE_NOT_ALLOWED)?;
+    settings::update(|s| apply(s, value)).map_err(|e| {
+        crate::log_warn!("settings save {:?}", e);
+        AttErrorCode::UNLIKELY_ERROR
+    })?;
+    *restart = true;
+    Ok(())
+}
+
+async fn notify_status(server: &Server<'_>, conn: &GattConnection<'_, '_, DefaultPacketPool>) {
+    loop {
+        Timer::after(STATUS_INTERVAL).await;
+        if let Err(e) = server.device.status.notify(conn, &status()).await {
+            crate::log_warn!("ble notify {:?}", e);
+            return;
+        }
+    }
+}
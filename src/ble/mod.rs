@@ -0,0 +1,91 @@
+use bt_hci::controller::ExternalController;
+use embassy_futures::join::{join, join3};
+use esp_hal::{peripherals::BT, rng::Rng};
+use esp_radio::ble::controller::BleConnector;
+use trouble_host::prelude::*;
+
+use crate::ethernet::MAC_ADDR;
+
+mod decode;
+mod gatt;
+mod observer;
+
+const CONNECTIONS_MAX: usize = 1;
+const L2CAP_CHANNELS_MAX: usize = 2;
+const HCI_SLOTS: usize = 20;
+
+/// Static random address derived from the device MAC. Random static addresses need the two most
+/// significant bits set, and trouble wants the bytes least significant first.
+fn address() -> Address {
+    let mut bytes = MAC_ADDR;
+    bytes.reverse();
+    bytes[5] |= 0xc0;
+    Address::random(bytes)
+}
+
+/// The hardware RNG samples RF noise, which makes it a true RNG while the radio is on, as it is
+/// for as long as BLE runs. The security manager takes its pairing keys from it.
+struct RadioRng(Rng);
+
+impl rand_core::RngCore for RadioRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.random()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.0.random() as u64) << 32 | self.0.random() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.read(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl rand_core::CryptoRng for RadioRng {}
+
+/// Runs the BLE host next to Wi-Fi on the shared radio: serves the GATT service and forwards
+/// sensor advertisements.
+#[embassy_executor::task]
+pub async fn ble_task(radio: &'static esp_radio::Controller<'static>, bt: BT<'static>) {
+    let connector = match BleConnector::new(radio, bt, Default::default()) {
+        Ok(connector) => connector,
+        Err(e) => {
+            crate::log_error!("ble init {:?}", e);
+            return;
+        }
+    };
+    let controller: ExternalController<_, HCI_SLOTS> = ExternalController::new(connector);
+    let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> =
+        HostResources::new();
+    let stack = trouble_host::new(controller, &mut resources)
+        .set_random_address(address())
+        .set_random_generator_seed(&mut RadioRng(Rng::new()))
+        .set_io_capabilities(gatt::io_capabilities());
+    let Host {
+        mut peripheral,
+        central,
+        mut runner,
+        ..
+    } = stack.build();
+
+    crate::log_info!("ble started");
+    join3(
+        async {
+            loop {
+                if let Err(e) = runner.run_with_handler(&observer::Reports).await {
+                    crate::log_error!("ble host {:?}", e);
+                }
+            }
+        },
+        gatt::serve(&mut peripheral),
+        async {
+            join(observer::scan(central), observer::forward()).await;
+        },
+    )
+    .await;
+}
@@ -0,0 +1,129 @@
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Timer, WithTimeout};
+use trouble_host::prelude::*;
+
+use crate::{bus, supervisor};
+
+use super::decode::{decode, uuids, Reading};
+
+/// `BLE_FILTER`: comma separated MACs (`aa:bb:cc:dd:ee:ff`) and 16-bit service UUIDs (`fcd2`).
+/// Empty forwards every device we can decode.
+const FILTER: &str = match option_env!("BLE_FILTER") {
+    Some(filter) => filter,
+    None => "",
+};
+/// Minimum time between two publishes of the same device, unless `BLE_INTERVAL` (seconds) says
+/// otherwise.
+const INTERVAL: Duration = Duration::from_secs(60);
+const MAX_DEVICES: usize = 32;
+const PAYLOAD_SIZE: usize = 320;
+/// How often `forward` checks in while no advertisements come.
+const CHECK_IN: Duration = Duration::from_secs(10);
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+struct Advert {
+    /// Most significant byte first.
+    mac: [u8; 6],
+    reading: Reading,
+}
+
+static ADVERTS: Channel<CriticalSectionRawMutex, Advert, 8> = Channel::new();
+
+fn allowed(mac: &[u8; 6], adv: &[u8]) -> bool {
+    let mut filters = FILTER
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .peekable();
+    if filters.peek().is_none() {
+        return true;
+    }
+    filters.any(|f| {
+        if f.len() == 4 {
+            u16::from_str_radix(f, 16).is_ok_and(|uuid| uuids(adv).any(|u| u == uuid))
+        } else {
+            let mut bytes = f.split(':').map(|b| u8::from_str_radix(b, 16));
+            mac.iter()
+                .all(|m| bytes.next().is_some_and(|b| b == Ok(*m)))
+                && bytes.next().is_none()
+        }
+    })
+}
+
+/// Receives advertising reports from the host runner. Runs inside the HCI event path, so it only
+/// decodes and hands off; anything that does not fit the queue is dropped.
+pub(super) struct Reports;
+
+impl EventHandler for Reports {
+    fn on_adv_reports(&self, mut reports: LeAdvReportsIter<'_>) {
+        while let Some(Ok(report)) = reports.next() {
+            let mut mac = [0u8; 6];
+            mac.copy_from_slice(report.addr.raw());
+            mac.reverse();
+            if !allowed(&mac, report.data) {
+                continue;
+            }
+            if let Some(mut reading) = decode(report.data) {
+                reading.rssi = report.rssi;
+                ADVERTS.try_send(Advert { mac, reading }).ok();
+            }
+        }
+    }
+}
+
+/// Keeps a passive scan running. Short windows leave the radio to Wi-Fi most of the time.
+pub(super) async fn scan<C: Controller>(central: Central<'_, C, DefaultPacketPool>) {
+    let mut scanner = Scanner::new(central);
+    let config = ScanConfig {
+        active: false,
+        interval: Duration::from_millis(200),
+        window: Duration::from_millis(50),
+        ..Default::default()
+    };
+    loop {
+        match scanner.scan(&config).await {
+            Ok(_session) => core::future::pending::<()>().await,
+            Err(e) => {
+                crate::log_warn!("ble scan {:?}", e);
+                Timer::after_secs(5).await;
+            }
+        }
+    }
+}
+
+fn interval() -> Duration {
+    option_env!("BLE_INTERVAL")
+        .and_then(|s| s.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(INTERVAL)
+}
+
+/// Publishes decoded readings on `/ble/<mac>`, at most once per `BLE_INTERVAL` per device.
+pub(super) async fn forward() {
+    let interval = interval();
+    let mut last: heapless::LinearMap<[u8; 6], Instant, MAX_DEVICES> = heapless::LinearMap::new();
+    let mut payload = crate::vec_in_myheap!(0u8; PAYLOAD_SIZE);
+    let heartbeat = supervisor::register("ble", None, STUCK_AFTER);
+    loop {
+        heartbeat.beat();
+        let Ok(advert) = ADVERTS.receive().with_timeout(CHECK_IN).await else {
+            continue;
+        };
+        let now = Instant::now();
+        if last.get(&advert.mac).is_some_and(|&t| now - t < interval) {
+            continue;
+        }
+        if last.insert(advert.mac, now).is_err() {
+            // full: forget the device heard longest ago
+            if let Some(oldest) = last.iter().min_by_key(|(_, &t)| t).map(|(m, _)| *m) {
+                last.remove(&oldest);
+                last.insert(advert.mac, now).ok();
+            }
+        }
+
+        let Ok(len) = serde_json_core::to_slice(&advert.reading, &mut payload[..]) else {
+            continue;
+        };
+        bus::send_or_drop(bus::Channel::Ble(advert.mac), &payload[..len]);
+    }
+}
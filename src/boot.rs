@@ -0,0 +1,161 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embedded_storage::{ReadStorage, Storage};
+use esp_hal::{
+    rtc_cntl::{reset_reason, SocResetReason},
+    system::Cpu,
+};
+use esp_storage::FlashStorage;
+use serde::{Deserialize, Serialize};
+
+use crate::{crash, settings};
+
+/// Marks a boot record written by this firmware, `"BOOT"`.
+const MAGIC: u32 = 0x424f_4f54;
+/// In the `nvs` partition, one sector past the settings.
+const RECORD_OFFSET: u32 = 0x1000;
+const HEADER_LEN: usize = 6;
+const RECORD_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, defmt::Format)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetCause {
+    PowerOn,
+    Brownout,
+    /// The task watchdog in `main`, or an RTC watchdog.
+    Watchdog,
+    Panic,
+    /// A software reset into a different firmware, which is how an update ends.
+    Ota,
+    /// Any other software reset, e.g. `/api/restart` or after provisioning.
+    Software,
+    Other,
+}
+
+impl ResetCause {
+    fn abnormal(self) -> bool {
+        matches!(
+            self,
+            ResetCause::Brownout | ResetCause::Watchdog | ResetCause::Panic | ResetCause::Other
+        )
+    }
+}
+
+/// Kept in flash across boots.
+#[derive(Default, Serialize, Deserialize)]
+struct Record {
+    boots: u32,
+    abnormal: u32,
+    git: heapless::String<8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BootInfo {
+    pub cause: ResetCause,
+    /// Boots since the record was first written.
+    pub boots: u32,
+    /// Abnormal resets in a row up to and including this boot, 0 after a normal one.
+    pub abnormal: u32,
+}
+
+static INFO: Mutex<CriticalSectionRawMutex, Cell<BootInfo>> = Mutex::new(Cell::new(BootInfo {
+    cause: ResetCause::Other,
+    boots: 0,
+    abnormal: 0,
+}));
+
+/// Whether the watchdog reset us, in which case nothing got a chance to run before.
+pub fn watchdog_reset() -> bool {
+    matches!(
+        reset_reason(Cpu::ProCpu),
+        Some(
+            SocResetReason::CoreMwdt0
+                | SocResetReason::CoreMwdt1
+                | SocResetReason::CoreRtcWdt
+                | SocResetReason::Cpu0Mwdt0
+                | SocResetReason::Cpu0RtcWdt
+                | SocResetReason::SysRtcWdt
+        )
+    )
+}
+
+fn load(flash: &mut FlashStorage, offset: u32) -> Record {
+    let mut buf = [0u8; RECORD_LEN];
+    if flash.read(offset, &mut buf).is_err() {
+        return Record::default();
+    }
+    let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let len = u16::from_le_bytes([buf[4], buf[5]]) as usize;
+    if magic != MAGIC || HEADER_LEN + len > RECORD_LEN {
+        return Record::default();
+    }
+    postcard::from_bytes(&buf[HEADER_LEN..HEADER_LEN + len]).unwrap_or_default()
+}
+
+fn store(flash: &mut FlashStorage, offset: u32, record: &Record) -> Result<(), ()> {
+    let mut buf = [0xffu8; RECORD_LEN];
+    let len = postcard::to_slice(record, &mut buf[HEADER_LEN..])
+        .map_err(|_| ())?
+        .len();
+    buf[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    buf[4..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+    flash
+        .write(offset, &buf[..HEADER_LEN + len])
+        .map_err(|_| ())
+}
+
+/// Works out why we restarted and counts the boot. Call once at boot, after `crash::init`.
+pub fn init() {
+    let mut flash = FlashStorage::new();
+    let Ok(offset) = settings::partition_offset(&mut flash) else {
+        crate::log_error!("boot record: no nvs partition");
+        return;
+    };
+    let mut record = load(&mut flash, offset + RECORD_OFFSET);
+    let git = env!("GIT_HASH");
+
+    let cause = if crash::panicked() {
+        ResetCause::Panic
+    } else if watchdog_reset() {
+        ResetCause::Watchdog
+    } else {
+        match reset_reason(Cpu::ProCpu) {
+            Some(SocResetReason::ChipPowerOn) => ResetCause::PowerOn,
+            Some(SocResetReason::SysBrownOut) => ResetCause::Brownout,
+            Some(SocResetReason::CoreSw | SocResetReason::Cpu0Sw) if record.git != git => {
+                ResetCause::Ota
+            }
+            Some(SocResetReason::CoreSw | SocResetReason::Cpu0Sw) => ResetCause::Software,
+            _ => ResetCause::Other,
+        }
+    };
+
+    record.boots = record.boots.wrapping_add(1);
+    record.abnormal = if cause.abnormal() {
+        record.abnormal + 1
+    } else {
+        0
+    };
+    record.git = heapless::String::try_from(git).unwrap_or_default();
+    if store(&mut flash, offset + RECORD_OFFSET, &record).is_err() {
+        crate::log_error!("boot record: could not save");
+    }
+
+    let info = BootInfo {
+        cause,
+        boots: record.boots,
+        abnormal: record.abnormal,
+    };
+    crate::log_info!(
+        "boot {} after {:?}, {} abnormal in a row",
+        info.boots,
+        info.cause,
+        info.abnormal
+    );
+    INFO.lock(|i| i.set(info));
+}
+
+pub fn info() -> BootInfo {
+    INFO.lock(|i| i.get())
+}
@@ -0,0 +1,71 @@
+// Shared with `host-tests`, which checks the topics. Keep esp-hal and defmt out of this file.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::iot_topic;
+
+/// What a message is about. Producers pick one of these; where it ends up (the MQTT topic, or
+/// the `topic` field of a webhook batch) is decided by `topic` below and nowhere else.
+#[derive(Debug, Clone, Copy)]
+pub enum Channel {
+    /// Log records, as JSON.
+    Logs,
+    /// The log buffer, on request.
+    LogDump,
+    /// The uplink in use, on every switch.
+    Link,
+    /// Frames from any TCP client.
+    Data,
+    /// The same frames again, by the client slot they came from.
+    DataSlot(usize),
+    /// Bytes received on a serial port.
+    Uart(usize),
+    /// The answer to an `/rpc/uart` transaction.
+    UartReply(usize),
+    /// Datagrams from the UDP peer.
+    Udp,
+    /// Readings of a BLE sensor, by MAC.
+    Ble([u8; 6]),
+    WifiState,
+    WifiScan,
+    OtaLog,
+    /// How the previous boot ended, if it crashed.
+    Crash,
+    /// Heap usage, periodically.
+    Memory,
+    /// Reply to `/echo`. Outside the device prefix, so it does not come back to us.
+    Echo,
+}
+
+impl Channel {
+    pub fn topic(&self) -> String<64> {
+        let mut topic = String::new();
+        let result = match self {
+            Channel::Echo => write!(topic, "/echo"),
+            Channel::Logs => write!(topic, concat!(iot_topic!(), "/logs")),
+            Channel::LogDump => write!(topic, concat!(iot_topic!(), "/logs/dump")),
+            Channel::Link => write!(topic, concat!(iot_topic!(), "/link")),
+            Channel::Data => write!(topic, concat!(iot_topic!(), "/data")),
+            Channel::DataSlot(slot) => write!(topic, concat!(iot_topic!(), "/data/{}"), slot),
+            Channel::Uart(port) => write!(topic, concat!(iot_topic!(), "/uart/{}"), port),
+            Channel::UartReply(port) => {
+                write!(topic, concat!(iot_topic!(), "/uart/{}/reply"), port)
+            }
+            Channel::Udp => write!(topic, concat!(iot_topic!(), "/udp")),
+            Channel::Ble(m) => write!(
+                topic,
+                concat!(iot_topic!(), "/ble/{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}"),
+                m[0], m[1], m[2], m[3], m[4], m[5]
+            ),
+            Channel::WifiState => write!(topic, concat!(iot_topic!(), "/wifi/state")),
+            Channel::WifiScan => write!(topic, concat!(iot_topic!(), "/wifi/scan")),
+            Channel::OtaLog => write!(topic, concat!(iot_topic!(), "/ota/log")),
+            Channel::Crash => write!(topic, concat!(iot_topic!(), "/crash")),
+            Channel::Memory => write!(topic, concat!(iot_topic!(), "/memory")),
+        };
+        result.expect("Topic too big");
+        topic
+    }
+}
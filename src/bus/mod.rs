@@ -0,0 +1,39 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub use channel::Channel;
+pub use transport::Transport;
+
+use crate::mqtt;
+
+mod channel;
+mod transport;
+
+/// The transports producers send through. Only the one configured takes messages: the webhook
+/// when `WEBHOOK_URL` is set, the broker otherwise.
+static TRANSPORT: (mqtt::Mqtt, mqtt::Webhook) = (mqtt::Mqtt, mqtt::Webhook);
+
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+pub async fn send(channel: Channel, payload: &[u8]) {
+    TRANSPORT.send(channel, payload).await;
+}
+
+/// Like `send`, but drops the message instead of waiting when the transport is backed up, for
+/// callers that must keep running while the uplink is down. Returns whether it was queued.
+pub fn try_send(channel: Channel, payload: &[u8]) -> bool {
+    TRANSPORT.try_send(channel, payload)
+}
+
+/// For bridges that must not stall while the uplink is down, since their task would stop
+/// checking in with the supervisor: queues the message if there is room and otherwise drops it,
+/// counted in `dropped`.
+pub fn send_or_drop(channel: Channel, payload: &[u8]) {
+    if !try_send(channel, payload) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Messages `send_or_drop` could not queue since boot.
+pub fn dropped() -> u32 {
+    DROPPED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,26 @@
+// Shared with `host-tests`, which runs it against an in-memory transport. Keep esp-hal and
+// defmt out of this file.
+
+use super::Channel;
+
+/// Somewhere messages can be delivered to. One that is not configured takes nothing and returns
+/// at once, so it can sit in `TRANSPORT` next to the others.
+pub trait Transport {
+    /// Queues `payload`, waiting for room.
+    async fn send(&self, channel: Channel, payload: &[u8]);
+    /// Queues `payload` unless that would mean waiting. Returns whether it was queued.
+    fn try_send(&self, channel: Channel, payload: &[u8]) -> bool;
+}
+
+/// Delivers to both transports.
+impl<A: Transport, B: Transport> Transport for (A, B) {
+    async fn send(&self, channel: Channel, payload: &[u8]) {
+        self.0.send(channel, payload).await;
+        self.1.send(channel, payload).await;
+    }
+
+    fn try_send(&self, channel: Channel, payload: &[u8]) -> bool {
+        let first = self.0.try_send(channel, payload);
+        self.1.try_send(channel, payload) || first
+    }
+}
@@ -0,0 +1,275 @@
+use core::{cell::RefCell, fmt::Write, mem::MaybeUninit, panic::PanicInfo};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::{
+    boot,
+    bus::{self, Channel},
+    supervisor,
+};
+
+/// Marks a record written by this firmware, `"CRS2"`. The RTC memory holding it is not
+/// cleared on reset and holds garbage after power-on.
+const MAGIC: u32 = 0x4352_5332;
+const MESSAGE_LEN: usize = 160;
+const FILE_LEN: usize = 64;
+const TASK_LEN: usize = 24;
+const MAX_FRAMES: usize = 10;
+const REPORT_LEN: usize = 640;
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+/// Text cut short at `N` bytes rather than failing, since there is nowhere to report a failure.
+#[derive(Clone, Copy)]
+struct Text<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Text<N> {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        let bytes = &self.bytes[..self.len.min(N)];
+        match core::str::from_utf8(bytes) {
+            Ok(s) => s,
+            // cut in the middle of a character
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+        }
+    }
+}
+
+impl<const N: usize> Write for Text<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(N - self.len);
+        self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// What survives a reset in RTC memory.
+#[derive(Clone, Copy)]
+struct Rtc {
+    magic: u32,
+    /// Uptime at the last `alive`, or at the panic.
+    uptime_ms: u64,
+    /// 1 after a panic. Not a `bool`, which could not hold whatever power-on leaves here.
+    panicked: u8,
+    git: [u8; 7],
+    message: Text<MESSAGE_LEN>,
+    file: Text<FILE_LEN>,
+    line: u32,
+    frames: [u32; MAX_FRAMES],
+    frame_count: usize,
+    /// The task the supervisor found stuck, after which the watchdog was left to reset us.
+    task: Text<TASK_LEN>,
+    /// `!magic`, written last so a record cut short by a reset is not taken as valid.
+    end: u32,
+}
+
+impl Rtc {
+    const fn new() -> Self {
+        Self {
+            magic: MAGIC,
+            uptime_ms: 0,
+            panicked: 0,
+            git: [0; 7],
+            message: Text::new(),
+            file: Text::new(),
+            line: 0,
+            frames: [0; MAX_FRAMES],
+            frame_count: 0,
+            task: Text::new(),
+            end: !MAGIC,
+        }
+    }
+
+    fn valid(&self) -> bool {
+        self.magic == MAGIC
+            && self.end == !MAGIC
+            && self.frame_count <= MAX_FRAMES
+            && self.message.len <= MESSAGE_LEN
+            && self.file.len <= FILE_LEN
+            && self.task.len <= TASK_LEN
+    }
+}
+
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut RTC: MaybeUninit<Rtc> = MaybeUninit::uninit();
+
+fn rtc_write(f: impl FnOnce(&mut Rtc)) {
+    critical_section::with(|_| {
+        // SAFETY: only touched inside a critical section, and every bit pattern is a valid
+        // `Rtc`; `valid` tells ours from garbage
+        let rtc = unsafe { (*core::ptr::addr_of_mut!(RTC)).assume_init_mut() };
+        if !rtc.valid() {
+            *rtc = Rtc::new();
+        }
+        rtc.end = 0;
+        f(rtc);
+        rtc.end = !MAGIC;
+    })
+}
+
+fn rtc_take() -> Option<Rtc> {
+    critical_section::with(|_| {
+        // SAFETY: as in `rtc_write`
+        let rtc = unsafe { (*core::ptr::addr_of_mut!(RTC)).assume_init_mut() };
+        let previous = rtc.valid().then_some(*rtc);
+        *rtc = Rtc::new();
+        previous
+    })
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    /// `panic` or `watchdog`.
+    kind: &'static str,
+    /// The firmware that crashed, which may not be the one reporting.
+    git: &'a str,
+    /// Milliseconds since boot; for a watchdog reset the last time the main loop ran.
+    uptime: u64,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    message: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    file: &'a str,
+    #[serde(skip_serializing_if = "is_zero")]
+    line: u32,
+    /// For a watchdog reset, the task that stopped checking in, if it was one of ours.
+    #[serde(skip_serializing_if = "str::is_empty")]
+    task: &'a str,
+    /// Return addresses, innermost first, for `addr2line`.
+    backtrace: heapless::Vec<heapless::String<10>, MAX_FRAMES>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+fn kind(crash: &Rtc) -> &'static str {
+    if crash.panicked == 1 {
+        "panic"
+    } else {
+        "watchdog"
+    }
+}
+
+/// The crash the previous boot ended with, if it did, waiting for `crash_task` to send it.
+static PENDING: Mutex<CriticalSectionRawMutex, RefCell<Option<Rtc>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Picks up what the previous boot left in RTC memory. Call once, early at boot.
+pub fn init() {
+    let previous = rtc_take();
+    let watchdog = boot::watchdog_reset();
+    let crash = match previous {
+        Some(rtc) if rtc.panicked == 1 => Some(rtc),
+        // the watchdog gives no chance to write anything, all we have is the last `alive`
+        Some(rtc) if watchdog => Some(rtc),
+        _ => None,
+    };
+    if let Some(crash) = &crash {
+        crate::log_warn!(
+            "previous boot crashed: {} {}",
+            kind(crash),
+            crash.message.as_str()
+        );
+    }
+    PENDING.lock(|p| p.replace(crash));
+}
+
+/// Whether the previous boot ended in a panic.
+pub fn panicked() -> bool {
+    PENDING.lock(|p| p.borrow().as_ref().is_some_and(|c| c.panicked == 1))
+}
+
+/// Records the uptime and firmware, for a report should the watchdog reset us.
+pub fn alive() {
+    rtc_write(|rtc| {
+        rtc.uptime_ms = Instant::now().as_millis();
+        rtc.git.copy_from_slice(&env!("GIT_HASH").as_bytes()[..7]);
+        rtc.task = Text::new();
+    });
+}
+
+/// Records the task that stopped checking in, for the report after the watchdog resets us.
+pub fn stuck(task: &str) {
+    rtc_write(|rtc| {
+        rtc.task = Text::new();
+        rtc.task.write_str(task).ok();
+    });
+}
+
+/// Sends the report of how the previous boot ended, if it crashed, on `/crash`. It goes out
+/// once the transport is connected.
+#[embassy_executor::task]
+pub async fn crash_task() {
+    let Some(crash) = PENDING.lock(|p| p.borrow_mut().take()) else {
+        return;
+    };
+    let heartbeat = supervisor::register("crash", None, STUCK_AFTER);
+    let mut backtrace = heapless::Vec::new();
+    for pc in &crash.frames[..crash.frame_count] {
+        let mut addr = heapless::String::new();
+        write!(addr, "{:#010x}", pc).ok();
+        backtrace.push(addr).ok();
+    }
+    let report = Report {
+        kind: kind(&crash),
+        git: core::str::from_utf8(&crash.git).unwrap_or_default(),
+        uptime: crash.uptime_ms,
+        message: crash.message.as_str(),
+        file: crash.file.as_str(),
+        line: crash.line,
+        task: crash.task.as_str(),
+        backtrace,
+    };
+    let mut payload = crate::vec_in_myheap!(0u8; REPORT_LEN);
+    match serde_json_core::to_slice(&report, &mut payload[..]) {
+        Ok(len) => {
+            // waits for the uplink, however long that takes
+            heartbeat.idle();
+            bus::send(Channel::Crash, &payload[..len]).await;
+        }
+        Err(_) => crate::log_error!("crash report does not fit"),
+    }
+}
+
+/// Saves the panic to RTC memory for the next boot to report, then restarts.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let backtrace = esp_backtrace::Backtrace::capture();
+    let frames = backtrace.frames();
+    rtc_write(|rtc| {
+        rtc.panicked = 1;
+        rtc.uptime_ms = Instant::now().as_millis();
+        rtc.git.copy_from_slice(&env!("GIT_HASH").as_bytes()[..7]);
+        rtc.message = Text::new();
+        write!(rtc.message, "{}", info.message()).ok();
+        rtc.file = Text::new();
+        rtc.line = 0;
+        if let Some(location) = info.location() {
+            rtc.file.write_str(location.file()).ok();
+            rtc.line = location.line();
+        }
+        rtc.task = Text::new();
+        rtc.frame_count = frames.len().min(MAX_FRAMES);
+        for (slot, frame) in rtc.frames.iter_mut().zip(frames) {
+            *slot = frame.program_counter() as u32;
+        }
+    });
+
+    defmt::error!("====================== PANIC ======================");
+    defmt::error!("{}", defmt::Display2Format(info));
+    for frame in frames {
+        defmt::error!("{=u32:#010x}", frame.program_counter() as u32);
+    }
+    esp_hal::system::software_reset()
+}
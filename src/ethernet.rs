@@ -1,6 +1,5 @@
 use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
 use embassy_executor::Spawner;
-use embassy_net::DhcpConfig;
 use embassy_net::Stack;
 use embassy_net::StackResources;
 use embassy_net_wiznet::Device;
@@ -9,6 +8,8 @@ use embassy_net_wiznet::State;
 use embassy_net_wiznet::chip::W5500;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::mutex::Mutex;
+use embedded_hal_async::spi::ErrorType;
+use embedded_hal_async::spi::Operation;
 use esp_hal::Async;
 use esp_hal::Blocking;
 use esp_hal::gpio::AnyPin;
@@ -22,14 +23,63 @@ use esp_hal::spi::master::Spi;
 use static_cell::ConstStaticCell;
 use static_cell::StaticCell;
 
+use crate::netcfg::{self, IfaceConfig};
+
 const N_RX: usize = 15;
 const N_TX: usize = 15;
-const MAC_ADDR: [u8; 6] = parse_mac(env!("MAC"));
+pub const MAC_ADDR: [u8; 6] = parse_mac(env!("MAC"));
+
+/// Address phase of Sn_MR, the mode register at the start of each socket register block.
+const SN_MR: [u8; 2] = [0, 0];
+/// Control phase of a write to the socket 0 register block.
+const SOCKET0_WRITE: u8 = (1 << 3) | (1 << 2);
+/// MAC filter enable in Sn_MR: in MACRAW mode, only frames for our MAC or broadcast get in.
+const MFEN: u8 = 1 << 7;
+
+type WiznetSpi =
+    MulticastSpi<SpiDevice<'static, CriticalSectionRawMutex, Spi<'static, Async>, Output<'static>>>;
+
+/// Clears MFEN when the driver puts socket 0 into MACRAW mode. With the filter on, the W5500
+/// drops multicast frames and mDNS queries to 224.0.0.251 never reach the stack; smoltcp
+/// filters by destination MAC itself, so nothing else changes.
+struct MulticastSpi<D>(D);
+
+impl<D: ErrorType> ErrorType for MulticastSpi<D> {
+    type Error = D::Error;
+}
+
+impl<D: embedded_hal_async::spi::SpiDevice> embedded_hal_async::spi::SpiDevice for MulticastSpi<D> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        if let [
+            Operation::Write(address),
+            Operation::Write(control),
+            Operation::Write(&[mode]),
+        ] = *operations
+        {
+            if address == SN_MR && control == [SOCKET0_WRITE] {
+                let mode = [mode & !MFEN];
+                return self
+                    .0
+                    .transaction(&mut [
+                        Operation::Write(address),
+                        Operation::Write(control),
+                        Operation::Write(&mode),
+                    ])
+                    .await;
+            }
+        }
+        self.0.transaction(operations).await
+    }
+}
 
 pub async fn ethernet_task(
     spi_peri: Spi<'static, Blocking>,
     cs: AnyPin<'static>,
     int: AnyPin<'static>,
+    config: IfaceConfig,
     spawner: Spawner,
 ) -> Stack<'static> {
     let rng = Rng::new();
@@ -42,29 +92,33 @@ pub async fn ethernet_task(
     let (wiznet, wiznet_runner) = embassy_net_wiznet::new::<N_RX, N_TX, W5500, _, _, _>(
         MAC_ADDR,
         STATE.take(),
-        SpiDevice::new(
+        MulticastSpi(SpiDevice::new(
             BUS.init(Mutex::<CriticalSectionRawMutex, Spi<'static, Async>>::new(
                 spi_dev,
             )),
             Output::new(cs, esp_hal::gpio::Level::High, OutputConfig::default()),
-        ),
+        )),
         Input::new(int, InputConfig::default()),
         NoPin,
     )
     .await
-    .inspect_err(|e| defmt::error!("{:#?}", defmt::Debug2Format(e)))
+    .inspect_err(|e| crate::log_error!("{:?}", e))
     .unwrap();
 
-    static SOCK: ConstStaticCell<StackResources<8>> = ConstStaticCell::new(StackResources::new());
+    static SOCK: ConstStaticCell<StackResources<{ netcfg::SOCKETS }>> =
+        ConstStaticCell::new(StackResources::new());
     let (stack, stack_runner) = embassy_net::new(
         wiznet,
-        embassy_net::Config::dhcpv4(DhcpConfig::default()),
+        config.initial(),
         SOCK.take(),
         (rng.random() as u64) << 32 | rng.random() as u64,
     );
 
     spawner.spawn(run_wiznet(wiznet_runner)).unwrap();
     spawner.spawn(run_stack(stack_runner)).unwrap();
+    spawner
+        .spawn(netcfg::dhcp_fallback_task(stack, config))
+        .unwrap();
 
     stack
 }
@@ -75,15 +129,7 @@ async fn run_stack(mut runner: embassy_net::Runner<'static, Device<'static>>) {
 }
 
 #[embassy_executor::task]
-async fn run_wiznet(
-    runner: Runner<
-        'static,
-        W5500,
-        SpiDevice<'static, CriticalSectionRawMutex, Spi<'static, Async>, Output<'static>>,
-        Input<'static>,
-        NoPin,
-    >,
-) {
+async fn run_wiznet(runner: Runner<'static, W5500, WiznetSpi, Input<'static>, NoPin>) {
     runner.run().await;
 }
 
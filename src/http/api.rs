@@ -0,0 +1,152 @@
+use core::fmt::Write;
+
+use picoserve::{
+    extract::FromRequestParts,
+    request::RequestParts,
+    response::{IntoResponse, Json, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{link, mqtt, output, settings, uart, MYHEAP};
+
+use super::RESTART;
+
+/// Requests carrying `Authorization: Bearer <TOKEN>`. Handlers that take it refuse anything else.
+pub(super) struct Authorized;
+
+impl<'r, State> FromRequestParts<'r, State> for Authorized {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        _state: &'r State,
+        request_parts: &RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        if authorized(request_parts) {
+            Ok(Authorized)
+        } else {
+            Err((StatusCode::UNAUTHORIZED, "unauthorized\n"))
+        }
+    }
+}
+
+pub(super) fn authorized(request_parts: &RequestParts<'_>) -> bool {
+    request_parts
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.as_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|token| same(token.as_bytes(), env!("TOKEN").as_bytes()))
+}
+
+/// Compares every byte of `expected` whatever `given` holds, so the response time does not tell
+/// how much of a guess was right. Only a wrong length is given away, and that is not secret.
+fn same(given: &[u8], expected: &[u8]) -> bool {
+    let mut diff = (given.len() != expected.len()) as u8;
+    for (i, e) in expected.iter().enumerate() {
+        diff |= e ^ given.get(i).copied().unwrap_or(0);
+    }
+    core::hint::black_box(diff) == 0
+}
+
+#[derive(Serialize)]
+struct Status {
+    id: &'static str,
+    org: &'static str,
+    git: &'static str,
+    link: &'static str,
+    ip: heapless::String<15>,
+    mqtt: bool,
+    relays: [bool; output::NUM_OUT],
+    heap_used: usize,
+    heap_size: usize,
+    uptime: u64,
+}
+
+pub(super) async fn status() -> impl IntoResponse {
+    let uplink = link::active();
+    let mut ip = heapless::String::new();
+    if let Some(config) = uplink.and_then(|u| u.stack.config_v4()) {
+        write!(ip, "{}", config.address.address()).ok();
+    }
+    let heap = MYHEAP.stats();
+    Json(Status {
+        id: env!("ID"),
+        org: env!("ORG"),
+        git: env!("GIT_HASH"),
+        link: uplink.map(|u| u.link.as_str()).unwrap_or("none"),
+        ip,
+        mqtt: mqtt::connected(),
+        relays: output::relays(),
+        heap_used: heap.current_usage,
+        heap_size: heap.size,
+        uptime: embassy_time::Instant::now().as_secs(),
+    })
+}
+
+pub(super) async fn get_relays(_: Authorized) -> impl IntoResponse {
+    Json(output::relays())
+}
+
+/// Same hex format as `/ctrl`.
+pub(super) async fn set_relays(_: Authorized, body: &str) -> impl IntoResponse {
+    output::output_state(output::parse_hex(body.trim())).await;
+    "ok\n"
+}
+
+/// Writes the body as is to the serial port.
+pub(super) async fn send_uart(port: usize, _: Authorized, body: &[u8]) -> impl IntoResponse {
+    if port >= uart::UART_PORTS {
+        return (StatusCode::NOT_FOUND, "no such port\n");
+    }
+    uart::uart_send(port, body).await;
+    (StatusCode::OK, "ok\n")
+}
+
+#[derive(Serialize)]
+struct ConfigView {
+    ssid: Option<heapless::String<32>>,
+    broker: Option<settings::Broker>,
+}
+
+/// The stored settings, without the Wi-Fi password.
+pub(super) async fn get_config(_: Authorized) -> impl IntoResponse {
+    let s = settings::get();
+    Json(ConfigView {
+        ssid: s.wifi.map(|w| w.ssid),
+        broker: s.broker,
+    })
+}
+
+#[derive(Deserialize)]
+struct ConfigUpdate {
+    wifi: Option<settings::WifiCredentials>,
+    broker: Option<settings::Broker>,
+}
+
+/// Stores `{"wifi":{"ssid","password"},"broker":{"host","port"}}`; either part may be left out.
+/// Takes effect after `/api/restart`.
+pub(super) async fn set_config(_: Authorized, body: &[u8]) -> impl IntoResponse {
+    let Ok((update, _)) = serde_json_core::from_slice::<ConfigUpdate>(body) else {
+        return (StatusCode::BAD_REQUEST, "invalid config\n");
+    };
+    let result = settings::update(|s| {
+        if update.wifi.is_some() {
+            s.wifi = update.wifi;
+        }
+        if update.broker.is_some() {
+            s.broker = update.broker;
+        }
+    });
+    match result {
+        Ok(()) => (StatusCode::OK, "ok\n"),
+        Err(e) => {
+            crate::log_error!("settings save {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "could not save\n")
+        }
+    }
+}
+
+pub(super) async fn restart(_: Authorized) -> impl IntoResponse {
+    RESTART.signal(());
+    "restarting\n"
+}
@@ -0,0 +1,87 @@
+use picoserve::{
+    io::Read,
+    request::{Request, RequestBodyConnection},
+    response::{IntoResponse, ResponseWriter, StatusCode},
+    routing::RequestHandlerService,
+    ResponseSent,
+};
+
+use crate::ota::{self, OtaError};
+
+use super::{api, RESTART};
+
+type Rejection = (StatusCode, &'static str);
+
+/// `POST /firmware`: the raw application image (as written by `espflash save-image`) is the
+/// body. It goes into the inactive OTA slot as it arrives, so it never has to fit in RAM.
+pub(super) struct Firmware;
+
+impl<State> RequestHandlerService<State> for Firmware {
+    async fn call_request_handler_service<R: Read, W: ResponseWriter<Error = R::Error>>(
+        &self,
+        _state: &State,
+        _path_parameters: (),
+        mut request: Request<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let result = if api::authorized(&request.parts) {
+            install(&mut request.body_connection).await?
+        } else {
+            Err((StatusCode::UNAUTHORIZED, "unauthorized\n"))
+        };
+        let connection = request.body_connection.finalize().await?;
+        match result {
+            Ok(()) => {
+                RESTART.signal(());
+                "installed, restarting\n"
+                    .write_to(connection, response_writer)
+                    .await
+            }
+            Err(rejection) => rejection.write_to(connection, response_writer).await,
+        }
+    }
+}
+
+fn reject(e: OtaError) -> Rejection {
+    crate::log_error!("firmware upload {:?}", e);
+    match e {
+        OtaError::Busy => (StatusCode::new(409), "update already running\n"),
+        OtaError::TooBig => (StatusCode::new(413), "image too large\n"),
+        OtaError::BadImage(_) => (StatusCode::BAD_REQUEST, "not an application image\n"),
+        OtaError::Checksum => (StatusCode::BAD_REQUEST, "checksum mismatch\n"),
+        OtaError::Incomplete => (StatusCode::BAD_REQUEST, "image incomplete\n"),
+        OtaError::Partition | OtaError::Flash => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "could not write image\n")
+        }
+    }
+}
+
+async fn install<R: Read>(
+    body: &mut RequestBodyConnection<'_, R>,
+) -> Result<Result<(), Rejection>, R::Error> {
+    let len = body.content_length();
+    if len > ota::MAX_IMAGE_LEN {
+        return Ok(Err(reject(OtaError::TooBig)));
+    }
+    let mut update = match ota::Update::begin() {
+        Ok(update) => update,
+        Err(e) => return Ok(Err(reject(e))),
+    };
+    crate::log_info!("firmware upload of {} bytes", len);
+
+    let mut reader = body.body().reader();
+    let mut buf = crate::vec_in_myheap!(0u8; 1024);
+    loop {
+        let n = reader.read(&mut buf[..]).await?;
+        if n == 0 {
+            break;
+        }
+        if let Err(e) = update.write(&buf[..n]) {
+            return Ok(Err(reject(e)));
+        }
+    }
+    if update.written() != len {
+        return Ok(Err(reject(OtaError::Incomplete)));
+    }
+    Ok(update.finish().map_err(reject))
+}
@@ -0,0 +1,114 @@
+use embassy_executor::Spawner;
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+use picoserve::{
+    response::File,
+    routing::{get, parse_path_segment, post, post_service},
+    AppBuilder, AppRouter,
+};
+
+use crate::{link, mk_static, supervisor};
+
+mod api;
+mod firmware;
+
+pub const HTTP_PORT: u16 = 80;
+pub const HTTP_TASKS: usize = 2;
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+/// Raised by `/api/restart` and after a firmware upload; the device restarts once the reply has gone out.
+static RESTART: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The status page, the REST API and firmware upload. Everything but the status needs the
+/// device token.
+struct App;
+
+impl AppBuilder for App {
+    type PathRouter = impl picoserve::routing::PathRouter;
+
+    fn build_app(self) -> picoserve::Router<Self::PathRouter> {
+        picoserve::Router::new()
+            .route(
+                "/",
+                get(|| async { File::html(include_str!("status.html")) }),
+            )
+            .route("/status", get(api::status))
+            .route("/api/relays", get(api::get_relays).post(api::set_relays))
+            .route(
+                ("/api/uart", parse_path_segment::<usize>()),
+                post(api::send_uart),
+            )
+            .route("/api/config", get(api::get_config).post(api::set_config))
+            .route("/api/restart", post(api::restart))
+            .route("/firmware", post_service(firmware::Firmware))
+    }
+}
+
+/// Serves the local status page and REST API on the active uplink.
+pub fn spawn(spawner: Spawner) {
+    let app = mk_static!(AppRouter<App>, App.build_app());
+    let config = mk_static!(
+        picoserve::Config<Duration>,
+        picoserve::Config::new(picoserve::Timeouts {
+            start_read_request: Some(Duration::from_secs(5)),
+            persistent_start_read_request: Some(Duration::from_secs(1)),
+            read_request: Some(Duration::from_secs(1)),
+            write: Some(Duration::from_secs(1)),
+        })
+        .keep_connection_alive()
+    );
+    for id in 0..HTTP_TASKS {
+        spawner
+            .spawn(http_task(id, app, config))
+            .inspect_err(|e| crate::log_error!("{:?}", e))
+            .unwrap();
+    }
+}
+
+#[embassy_executor::task(pool_size = HTTP_TASKS)]
+async fn http_task(
+    id: usize,
+    app: &'static AppRouter<App>,
+    config: &'static picoserve::Config<Duration>,
+) {
+    let mut tcp_rx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut tcp_tx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut http_buffer = crate::vec_in_myheap!(0u8; 2048);
+    let mut uplink = link::receiver();
+    let heartbeat = supervisor::register("http", Some(id), STUCK_AFTER);
+
+    loop {
+        // waiting for clients is normal, and picoserve's timeouts bound every request
+        heartbeat.idle();
+        let stack = uplink.get().await.stack;
+        let serve = picoserve::listen_and_serve(
+            id,
+            app,
+            config,
+            stack,
+            HTTP_PORT,
+            &mut tcp_rx_buffer[..],
+            &mut tcp_tx_buffer[..],
+            &mut http_buffer[..],
+        );
+        // only the first task restarts, the others just wait
+        let restart = async {
+            if id == 0 {
+                RESTART.wait().await;
+            } else {
+                core::future::pending::<()>().await;
+            }
+        };
+        let event = select3(serve, uplink.changed(), restart).await;
+        heartbeat.beat();
+        match event {
+            Either3::Third(_) => {
+                crate::log_info!("restart requested over http");
+                Timer::after_secs(1).await;
+                esp_hal::system::software_reset();
+            }
+            _ => crate::log_info!("uplink changed, restarting http server {}", id),
+        }
+    }
+}
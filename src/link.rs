@@ -0,0 +1,168 @@
+use embassy_net::Stack;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    watch::{Receiver, Watch},
+};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::{
+    bus::{self, Channel},
+    led, supervisor,
+};
+
+/// How often both interfaces are checked.
+const POLL: Duration = Duration::from_millis(500);
+/// The active uplink must be down this long before we move away from it.
+const FAILOVER_AFTER: Duration = Duration::from_secs(3);
+/// A preferred uplink must be up this long before we move back to it.
+const FAILBACK_AFTER: Duration = Duration::from_secs(30);
+const MAX_RECEIVERS: usize = 12;
+/// Every poll checks in, so this is many missed polls.
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub enum Link {
+    Ethernet,
+    Wifi,
+}
+
+impl Link {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Link::Ethernet => "ethernet",
+            Link::Wifi => "wifi",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Uplink {
+    pub link: Link,
+    pub stack: Stack<'static>,
+}
+
+static UPLINK: Watch<CriticalSectionRawMutex, Uplink, MAX_RECEIVERS> = Watch::new();
+
+pub type UplinkReceiver = Receiver<'static, CriticalSectionRawMutex, Uplink, MAX_RECEIVERS>;
+
+/// Follows the active uplink: `get` waits for the first one, `changed` for a switch. Tasks that
+/// talk to the outside world should drop their sockets and start over on the new stack when it
+/// changes.
+pub fn receiver() -> UplinkReceiver {
+    UPLINK.receiver().expect("too many uplink receivers")
+}
+
+/// The uplink currently in use, if any.
+pub fn active() -> Option<Uplink> {
+    UPLINK.try_get()
+}
+
+struct Health {
+    link: Link,
+    stack: Stack<'static>,
+    /// When the interface last went from unusable to usable, or `None` while unusable.
+    up_since: Option<Instant>,
+    /// When the interface last went from usable to unusable, or `None` while usable.
+    down_since: Option<Instant>,
+}
+
+impl Health {
+    fn new(link: Link, stack: Stack<'static>) -> Self {
+        Self {
+            link,
+            stack,
+            up_since: None,
+            down_since: Some(Instant::now()),
+        }
+    }
+
+    fn update(&mut self, now: Instant) {
+        let up = self.stack.is_link_up() && self.stack.config_v4().is_some();
+        match (up, self.up_since) {
+            (true, None) => {
+                self.up_since = Some(now);
+                self.down_since = None;
+            }
+            (false, Some(_)) => {
+                self.up_since = None;
+                self.down_since = Some(now);
+            }
+            _ => {}
+        }
+    }
+
+    fn up_for(&self, now: Instant, d: Duration) -> bool {
+        self.up_since.is_some_and(|t| now - t >= d)
+    }
+
+    fn down_for(&self, now: Instant, d: Duration) -> bool {
+        self.down_since.is_some_and(|t| now - t >= d)
+    }
+}
+
+/// Picks the uplink from `links`, listed in order of preference, given the current one.
+fn choose(links: &[Health], active: Option<Link>, now: Instant) -> Option<usize> {
+    let current = links.iter().position(|h| Some(h.link) == active);
+    let Some(current) = current else {
+        return links.iter().position(|h| h.up_since.is_some());
+    };
+
+    // fail back to a more preferred interface once it has proven stable
+    if let Some(better) = links[..current]
+        .iter()
+        .position(|h| h.up_for(now, FAILBACK_AFTER))
+    {
+        return Some(better);
+    }
+    if !links[current].down_for(now, FAILOVER_AFTER) {
+        return Some(current);
+    }
+    links
+        .iter()
+        .position(|h| h.up_since.is_some())
+        .or(Some(current))
+}
+
+/// Watches both interfaces and publishes the one MQTT and the bridges should use. Ethernet is
+/// preferred; the switch is reported on `/link`.
+#[embassy_executor::task]
+pub async fn link_task(ethernet: Stack<'static>, wifi: Stack<'static>) {
+    let sender = UPLINK.sender();
+    let mut links = [
+        Health::new(Link::Ethernet, ethernet),
+        Health::new(Link::Wifi, wifi),
+    ];
+    let mut active: Option<Link> = None;
+    let heartbeat = supervisor::register("link", None, STUCK_AFTER);
+
+    loop {
+        heartbeat.beat();
+        let now = Instant::now();
+        for health in links.iter_mut() {
+            health.update(now);
+        }
+
+        if let Some(i) = choose(&links, active, now) {
+            let link = links[i].link;
+            if active != Some(link) {
+                crate::log_info!("uplink: {:?} -> {:?}", active, link);
+                active = Some(link);
+                sender.send(Uplink {
+                    link,
+                    stack: links[i].stack,
+                });
+                led::state(led::LedState::Ok).await;
+                // never block here: while offline the queue fills up and we are the one who can fix it
+                bus::try_send(Channel::Link, link.as_str().as_bytes());
+                crate::log_info!(
+                    "Link & config up: {:?} {:?} {}",
+                    links[i].stack.config_v4(),
+                    links[i].stack.hardware_address(),
+                    link.as_str()
+                );
+            }
+        }
+
+        Timer::after(POLL).await;
+    }
+}
@@ -0,0 +1,337 @@
+use core::{
+    cell::RefCell,
+    fmt::Write,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use serde::Serialize;
+
+use crate::{
+    bus::{self, Channel},
+    supervisor::{self, Heartbeat},
+    MyHeapAllocator, MyHeapVec, MYHEAP,
+};
+
+/// Records kept in PSRAM, the oldest are overwritten.
+const CAPACITY: usize = 512;
+const MSG_LEN: usize = 160;
+const JSON_LEN: usize = 384;
+/// Forwarded records per second once the burst is used up.
+const RATE_PER_SEC: u32 = 5;
+const BURST: u32 = 20;
+/// How long to wait before trying again when the transport queue is full.
+const RETRY: Duration = Duration::from_secs(1);
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Record {
+    seq: u32,
+    /// Milliseconds since boot.
+    t: u64,
+    level: Level,
+    module: &'static str,
+    msg: heapless::String<MSG_LEN>,
+}
+
+#[derive(Serialize)]
+struct Forwarded<'a> {
+    seq: u32,
+    t: u64,
+    level: Level,
+    module: &'a str,
+    msg: &'a str,
+    /// Records left out by the rate limit (or lost to the buffer) since the previous one.
+    #[serde(skip_serializing_if = "is_zero")]
+    suppressed: u32,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+struct Ring {
+    records: MyHeapVec<Record>,
+    next_seq: u32,
+}
+
+impl Ring {
+    fn push(&mut self, mut record: Record) {
+        record.seq = self.next_seq;
+        if self.records.len() < CAPACITY {
+            self.records.push(record);
+        } else {
+            self.records[self.next_seq as usize % CAPACITY] = record;
+        }
+        self.next_seq += 1;
+    }
+
+    fn oldest(&self) -> u32 {
+        self.next_seq - self.records.len() as u32
+    }
+
+    fn get(&self, seq: u32) -> Option<Record> {
+        (seq >= self.oldest() && seq < self.next_seq)
+            .then(|| self.records[seq as usize % CAPACITY].clone())
+    }
+}
+
+static RING: Mutex<CriticalSectionRawMutex, RefCell<Option<Ring>>> = Mutex::new(RefCell::new(None));
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+/// Raised for every record, wakes the forwarder.
+static NEW: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Raised by `/rpc/log dump`.
+static DUMP: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Records from the `log_*!` macros go to defmt right away and are kept in a PSRAM ring
+/// buffer; those at or above the forward level are sent to `/logs` as JSON. Call once at boot,
+/// after the heap is set up. Records made before that, or all of them when PSRAM has no room
+/// for the ring, only reach defmt.
+pub fn init() {
+    let level = option_env!("LOG_LEVEL")
+        .and_then(Level::from_str)
+        .unwrap_or(Level::Info);
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    let mut records = MyHeapVec::new_in(MyHeapAllocator(&MYHEAP));
+    if records.try_reserve_exact(CAPACITY).is_err() {
+        // defmt only: there is no ring to keep the record in
+        defmt::warn!(
+            "logger: no room for {} records, logging to defmt only",
+            CAPACITY
+        );
+        return;
+    }
+    RING.lock(|r| {
+        r.replace(Some(Ring {
+            records,
+            next_seq: 0,
+        }))
+    });
+}
+
+fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Debug,
+        1 => Level::Info,
+        2 => Level::Warn,
+        _ => Level::Error,
+    }
+}
+
+/// Backs the `log_*!` macros.
+pub fn record(level: Level, module: &'static str, args: core::fmt::Arguments) {
+    let module = module.split_once("::").map_or(module, |(_, m)| m);
+    let mut msg = heapless::String::new();
+    // too long messages are cut short
+    msg.write_fmt(args).ok();
+    match level {
+        Level::Debug => defmt::debug!("[{=str}] {=str}", module, msg.as_str()),
+        Level::Info => defmt::info!("[{=str}] {=str}", module, msg.as_str()),
+        Level::Warn => defmt::warn!("[{=str}] {=str}", module, msg.as_str()),
+        Level::Error => defmt::error!("[{=str}] {=str}", module, msg.as_str()),
+    }
+    let record = Record {
+        seq: 0,
+        t: Instant::now().as_millis(),
+        level,
+        module,
+        msg,
+    };
+    RING.lock(|r| {
+        if let Some(ring) = r.borrow_mut().as_mut() {
+            ring.push(record);
+        }
+    });
+    NEW.signal(());
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::logger::record($crate::logger::Level::Debug, module_path!(), format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::logger::record($crate::logger::Level::Info, module_path!(), format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        $crate::logger::record($crate::logger::Level::Warn, module_path!(), format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::logger::record($crate::logger::Level::Error, module_path!(), format_args!($($arg)*))
+    };
+}
+
+/// `error`, `warn`, `info` or `debug` sets the level records are forwarded at; `dump` sends
+/// everything still in the buffer to `/logs/dump`, whatever its level.
+pub fn log_cmd_from_mqtt(payload: &[u8]) {
+    let cmd = core::str::from_utf8(payload).unwrap_or_default().trim();
+    if cmd == "dump" {
+        DUMP.signal(());
+    } else if let Some(level) = Level::from_str(cmd) {
+        LEVEL.store(level as u8, Ordering::Relaxed);
+        crate::log_info!("forwarding logs at {:?} and above", level);
+    } else {
+        crate::log_warn!("unknown log command {}", payload.escape_ascii());
+    }
+}
+
+fn get(seq: u32) -> (u32, Option<Record>) {
+    RING.lock(|r| match r.borrow().as_ref() {
+        Some(ring) => (ring.oldest(), ring.get(seq)),
+        None => (0, None),
+    })
+}
+
+/// Sends the records that were in the buffer when the dump was asked for; those made while it
+/// runs, like the ones publishing it, wait for the next dump.
+async fn dump(heartbeat: &Heartbeat) {
+    let mut json = crate::vec_in_myheap!(0u8; JSON_LEN);
+    let end = RING.lock(|r| r.borrow().as_ref().map_or(0, |ring| ring.next_seq));
+    let (mut seq, _) = get(0);
+    while seq < end {
+        let (oldest, record) = get(seq);
+        let Some(record) = record else {
+            // overwritten while we were sending
+            seq = oldest;
+            continue;
+        };
+        if let Ok(len) = serde_json_core::to_slice(&record, &mut json[..]) {
+            // waits while the uplink is down
+            heartbeat.idle();
+            bus::send(Channel::LogDump, &json[..len]).await;
+            heartbeat.beat();
+        }
+        seq += 1;
+    }
+}
+
+struct Bucket {
+    tokens: u32,
+    last: Instant,
+}
+
+impl Bucket {
+    fn take(&mut self) -> bool {
+        let refill = self.last.elapsed().as_millis() * RATE_PER_SEC as u64 / 1000;
+        if refill > 0 {
+            self.tokens = (self.tokens + refill as u32).min(BURST);
+            self.last = Instant::now();
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
+/// Forwards records at or above the configured level to `/logs`. While the transport is
+/// backed up it waits rather than skipping, so records from an outage go out once it clears,
+/// as long as they are still in the buffer.
+#[embassy_executor::task]
+pub async fn log_task() {
+    let mut json = crate::vec_in_myheap!(0u8; JSON_LEN);
+    let mut next = 0;
+    let mut suppressed = 0;
+    let mut stalled = false;
+    let mut bucket = Bucket {
+        tokens: BURST,
+        last: Instant::now(),
+    };
+    let heartbeat = supervisor::register("logger", None, STUCK_AFTER);
+
+    loop {
+        let retry = async {
+            if stalled {
+                Timer::after(RETRY).await;
+            } else {
+                core::future::pending::<()>().await;
+            }
+        };
+        heartbeat.idle();
+        let woken = select3(NEW.wait(), DUMP.wait(), retry).await;
+        heartbeat.beat();
+        if let Either3::Second(_) = woken {
+            dump(&heartbeat).await;
+        }
+        stalled = false;
+
+        loop {
+            let (oldest, record) = get(next);
+            if next < oldest {
+                // overwritten before we got to it
+                suppressed += oldest - next;
+                next = oldest;
+                continue;
+            }
+            let Some(record) = record else {
+                break;
+            };
+            if record.level < level() {
+                next += 1;
+                continue;
+            }
+            if !bucket.take() {
+                suppressed += 1;
+                next += 1;
+                continue;
+            }
+            let forwarded = Forwarded {
+                seq: record.seq,
+                t: record.t,
+                level: record.level,
+                module: record.module,
+                msg: &record.msg,
+                suppressed,
+            };
+            if let Ok(len) = serde_json_core::to_slice(&forwarded, &mut json[..]) {
+                if !bus::try_send(Channel::Logs, &json[..len]) {
+                    // give the token back, this one goes out on the next try
+                    bucket.tokens = (bucket.tokens + 1).min(BURST);
+                    stalled = true;
+                    break;
+                }
+            }
+            suppressed = 0;
+            next += 1;
+        }
+    }
+}
@@ -8,10 +8,9 @@
 
 use core::cell::LazyCell;
 
-use alloc::format;
-use defmt::{info, Debug2Format};
+use defmt::info;
 use embassy_executor::Spawner;
-use embassy_time::{Duration, Timer, WithTimeout};
+use embassy_time::Timer;
 use esp_alloc::{HeapRegion, MemoryCapability};
 use esp_hal::gpio::{Output, Pin};
 use esp_hal::spi;
@@ -20,20 +19,34 @@ use esp_hal::timer::timg::{MwdtStage, TimerGroup};
 use esp_hal::clock::CpuClock;
 use esp_rtos::main;
 use ethernet::ethernet_task;
-use mqtt::{mqtt_send, mqtt_task};
+use mqtt::mqtt_task;
 use output::output_task;
 use tcp::tcp_task;
 use uart::uart_task;
 use {esp_backtrace as _, esp_println as _};
 
 extern crate alloc;
+mod ble;
+mod boot;
+mod bus;
+mod crash;
 mod ethernet;
+mod http;
 mod led;
+mod link;
+mod logger;
+mod mdns;
+mod memory;
 mod mqtt;
 mod myheap;
+mod netcfg;
+mod ota;
 mod output;
+mod settings;
+mod supervisor;
 mod tcp;
 mod uart;
+mod udp;
 mod wifi;
 
 pub use myheap::{MyHeapAllocator, MyHeapVec, MYHEAP};
@@ -84,6 +97,11 @@ async fn main(spawner: Spawner) {
 
     info!("Heap initialized!");
 
+    settings::init();
+    logger::init();
+    crash::init();
+    boot::init();
+
     let timer0 = TimerGroup::new(peripherals.TIMG1);
     esp_rtos::start(timer0.timer0);
 
@@ -96,7 +114,7 @@ async fn main(spawner: Spawner) {
     watchdog.enable();
     watchdog.feed();
 
-    info!("Embassy initialized!");
+    crate::log_info!("Embassy initialized!");
 
     spawner
         .spawn(led::task(peripherals.GPIO2.degrade()))
@@ -138,16 +156,36 @@ async fn main(spawner: Spawner) {
             .with_rx(peripherals.GPIO25)
             .into_async();
         uart0.set_at_cmd(esp_hal::uart::AtCmdConfig::default().with_cmd_char(0x04));
-        let de_pin = Output::new(
-            peripherals.GPIO13,
-            esp_hal::gpio::Level::Low,
-            Default::default(),
-        );
-        spawner.spawn(uart_task(uart0, de_pin)).unwrap();
-        info!("Uart initialized");
+        let rs485 = uart::Rs485Config::default();
+        // RS485_DE_PIN, checked by build.rs against the pins nothing else uses
+        let de_pin = match option_env!("RS485_DE_PIN") {
+            Some("4") => peripherals.GPIO4.degrade(),
+            Some("12") => peripherals.GPIO12.degrade(),
+            Some("15") => peripherals.GPIO15.degrade(),
+            Some("22") => peripherals.GPIO22.degrade(),
+            _ => peripherals.GPIO13.degrade(),
+        };
+        let idle = if rs485.de_active_high {
+            esp_hal::gpio::Level::Low
+        } else {
+            esp_hal::gpio::Level::High
+        };
+        let de_pin = Output::new(de_pin, idle, Default::default());
+        let line = uart::Line::Rs485(de_pin, rs485);
+        spawner.spawn(uart_task(0, uart0, line)).unwrap();
+
+        let uart1 = esp_hal::uart::Uart::new(peripherals.UART2, config)
+            .unwrap()
+            .with_tx(peripherals.GPIO32)
+            .with_rx(peripherals.GPIO33)
+            .into_async();
+        spawner
+            .spawn(uart_task(1, uart1, uart::Line::Rs232))
+            .unwrap();
+        crate::log_info!("Uart initialized");
     }
 
-    let mut stack = {
+    let ethernet = {
         let mut spi_cfg = spi::master::Config::default();
         spi_cfg = spi_cfg.with_frequency(Rate::from_hz(1_000_000));
         let mut spi = spi::master::Spi::new(peripherals.SPI3, spi_cfg).unwrap();
@@ -160,68 +198,71 @@ async fn main(spawner: Spawner) {
             spi,
             peripherals.GPIO5.degrade(),
             peripherals.GPIO21.degrade(),
+            netcfg::ethernet(),
             spawner.clone(),
         )
     }
     .await;
-
-    led::state(led::LedState::Ok).await;
-
-    let wifi = stack
-        .wait_link_up()
-        .with_timeout(Duration::from_secs(2))
-        .await
-        .is_err();
-    if wifi {
-        stack = wifi::wifi_stack(
-            peripherals.WIFI,
-            spawner.clone(),
-        )
-        .await;
-    }
     watchdog.feed();
 
-    defmt::info!("Waiting link...");
-    stack.wait_link_up().await;
-    watchdog.feed();
-
-    led::state(led::LedState::Ok).await;
+    // Wi-Fi and BLE share the radio
+    let radio = &*mk_static!(esp_radio::Controller<'static>, esp_radio::init().unwrap());
 
-    defmt::info!(
-        "Waiting config... {:?}",
-        Debug2Format(&stack.hardware_address())
-    );
-    stack.wait_config_up().await;
-    defmt::info!("{:?}", defmt::Debug2Format(&stack.config_v4()));
+    // Wi-Fi is kept up next to Ethernet so the link manager can fail over to it
+    let wifi = wifi::wifi_stack(radio, peripherals.WIFI, netcfg::wifi(), spawner.clone()).await;
     watchdog.feed();
+    spawner.spawn(ble::ble_task(radio, peripherals.BT)).unwrap();
 
     led::state(led::LedState::Ok).await;
-    mqtt_send(
-        format!(
-            "Link & config up: {:?} {:?} {:?}",
-            stack.config_v4(),
-            stack.hardware_address(),
-            wifi
-        )
-        .as_bytes(),
-        concat!(iot_topic!(), "/logs"),
-    )
-    .await;
-
-    spawner.spawn(tcp_task(stack.clone())).unwrap();
-    spawner.spawn(mqtt_task(stack.clone())).unwrap();
-    //spawner.spawn(ota_task()).unwrap();
+    spawner.spawn(link::link_task(ethernet, wifi)).unwrap();
 
-    loop {
+    for (id, (stack, link)) in [(ethernet, link::Link::Ethernet), (wifi, link::Link::Wifi)]
+        .into_iter()
+        .enumerate()
+    {
+        spawner
+            .spawn(udp::discovery_task(stack, link.as_str()))
+            .unwrap();
+        spawner.spawn(mdns::mdns_task(id, stack)).unwrap();
+    }
 
-        // Print heap stats
-        info!(
-            "Heap usage: {:?}",
-            Debug2Format(&MYHEAP.stats())
-        );
+    let tcp_config = tcp::TcpConfig::default();
+    for slot in 0..tcp_config.clients {
+        spawner.spawn(tcp_task(slot, tcp_config)).unwrap();
+    }
+    if tcp_config.remote.is_some() {
+        spawner
+            .spawn(tcp::tcp_client_task(tcp_config.clients, tcp_config))
+            .unwrap();
+    }
+    spawner
+        .spawn(udp::udp_task(udp::UdpConfig::default()))
+        .unwrap();
+    spawner.spawn(logger::log_task()).unwrap();
+    // without a broker, messages go out as webhook POSTs instead
+    if mqtt::webhook_enabled() {
+        spawner.spawn(mqtt::webhook_task()).unwrap();
+    } else {
+        spawner.spawn(mqtt_task()).unwrap();
+    }
+    http::spawn(spawner);
+    spawner.spawn(ota::ota_task()).unwrap();
+    spawner.spawn(crash::crash_task()).unwrap();
+    spawner.spawn(memory::memory_task()).unwrap();
 
+    loop {
         Timer::after_secs(10).await;
-        watchdog.feed();
+        // a task that stopped checking in gets us reset by the watchdog, unless it recovers
+        match supervisor::stuck() {
+            None => {
+                watchdog.feed();
+                crash::alive();
+            }
+            Some(task) => {
+                crate::log_error!("task {} is stuck, not feeding the watchdog", task);
+                crash::stuck(&task);
+            }
+        }
     }
 
     // for inspiration have a look at the examples at https://github.com/esp-rs/esp-hal/tree/esp-hal-v1.0.0-beta.0/examples/src/bin
@@ -0,0 +1,322 @@
+use alloc::format;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpAddress, IpEndpoint, Ipv4Address, Stack,
+};
+use embassy_time::{Duration, Timer};
+
+use crate::supervisor;
+
+/// One per network stack.
+pub const MDNS_TASKS: usize = 2;
+/// Answering a query takes a send; anything longer is stuck.
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+const MDNS_ADDR: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const TTL: u32 = 120;
+const PACKET_SIZE: usize = 512;
+const NAME_LEN: usize = 128;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on records that are unique to this host so caches replace rather than append.
+const CACHE_FLUSH: u16 = 0x8000;
+
+const SERVICES_META: &str = "_services._dns-sd._udp.local";
+
+/// A DNS-SD service advertised as `<ID>.<kind>.local`.
+pub struct Service {
+    pub kind: &'static str,
+    pub port: u16,
+}
+
+pub fn services() -> [Service; 2] {
+    [
+        Service {
+            kind: "_mqtt-bridge._tcp",
+            port: crate::tcp::TcpConfig::default().port,
+        },
+        Service {
+            kind: "_http._tcp",
+            port: crate::http::HTTP_PORT,
+        },
+    ]
+}
+
+/// Answers mDNS for `<ID>.local` and the DNS-SD records of `services` on `stack`, and announces
+/// them once when started. Spawn one per network stack, numbered from 0.
+#[embassy_executor::task(pool_size = MDNS_TASKS)]
+pub async fn mdns_task(id: usize, stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = crate::vec_in_myheap!(0u8; 2 * PACKET_SIZE);
+    let mut tx_buffer = crate::vec_in_myheap!(0u8; 2 * PACKET_SIZE);
+    let mut query = crate::vec_in_myheap!(0u8; PACKET_SIZE);
+    let mut reply = crate::vec_in_myheap!(0u8; PACKET_SIZE);
+
+    let heartbeat = supervisor::register("mdns", Some(id), STUCK_AFTER);
+    heartbeat.idle();
+    stack.wait_config_up().await;
+    heartbeat.beat();
+    if let Err(e) = stack.join_multicast_group(MDNS_ADDR) {
+        crate::log_error!("mdns join {:?}", e);
+        return;
+    }
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer[..],
+        &mut tx_meta,
+        &mut tx_buffer[..],
+    );
+    if let Err(e) = socket.bind(MDNS_PORT) {
+        crate::log_error!("mdns bind {:?}", e);
+        return;
+    }
+    let group = IpEndpoint::new(IpAddress::Ipv4(MDNS_ADDR), MDNS_PORT);
+
+    // announce twice, a second apart, as RFC 6762 8.3 asks
+    for _ in 0..2 {
+        if let Some(ip) = address(stack) {
+            let len = announce(ip, &mut reply[..]);
+            if len > 0 {
+                socket.send_to(&reply[..len], group).await.ok();
+            }
+        }
+        Timer::after_secs(1).await;
+    }
+
+    loop {
+        heartbeat.idle();
+        let received = socket.recv_from(&mut query[..]).await;
+        heartbeat.beat();
+        let (len, meta) = match received {
+            Ok(r) => r,
+            Err(e) => {
+                crate::log_warn!("mdns recv {:?}", e);
+                continue;
+            }
+        };
+        let Some(ip) = address(stack) else {
+            continue;
+        };
+        let len = match answer(&query[..len], ip, &mut reply[..]) {
+            Some(len) => len,
+            None => continue,
+        };
+        // queries not sent from 5353 come from plain unicast resolvers and expect a direct reply
+        let to = if meta.endpoint.port == MDNS_PORT {
+            group
+        } else {
+            meta.endpoint
+        };
+        if let Err(e) = socket.send_to(&reply[..len], to).await {
+            crate::log_warn!("mdns send {:?}", e);
+        }
+    }
+}
+
+fn address(stack: Stack<'static>) -> Option<Ipv4Address> {
+    stack.config_v4().map(|c| c.address.address())
+}
+
+/// Serialises DNS records into a response packet.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    answers: u16,
+    ok: bool,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8], id: u16) -> Self {
+        let mut w = Self {
+            buf,
+            len: 0,
+            answers: 0,
+            ok: true,
+        };
+        w.u16(id);
+        w.u16(0x8400); // response, authoritative
+        w.u16(0);
+        w.u16(0);
+        w.u16(0);
+        w.u16(0);
+        w
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        if self.len + data.len() > self.buf.len() {
+            self.ok = false;
+            return;
+        }
+        self.buf[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_be_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_be_bytes());
+    }
+
+    fn name(&mut self, name: &str) {
+        for label in name.split('.') {
+            self.bytes(&[label.len() as u8]);
+            self.bytes(label.as_bytes());
+        }
+        self.bytes(&[0]);
+    }
+
+    /// Writes a record header, lets `rdata` fill the data, then patches the data length.
+    fn record(&mut self, name: &str, rtype: u16, class: u16, rdata: impl FnOnce(&mut Self)) {
+        self.name(name);
+        self.u16(rtype);
+        self.u16(class);
+        self.u32(TTL);
+        let at = self.len;
+        self.u16(0);
+        rdata(self);
+        if self.ok {
+            let rdlen = (self.len - at - 2) as u16;
+            self.buf[at..at + 2].copy_from_slice(&rdlen.to_be_bytes());
+            self.answers += 1;
+        }
+    }
+
+    fn a(&mut self, host: &str, ip: Ipv4Address) {
+        self.record(host, TYPE_A, CLASS_IN | CACHE_FLUSH, |w| {
+            w.bytes(&ip.octets())
+        });
+    }
+
+    fn ptr(&mut self, name: &str, target: &str) {
+        self.record(name, TYPE_PTR, CLASS_IN, |w| w.name(target));
+    }
+
+    fn srv(&mut self, instance: &str, host: &str, port: u16) {
+        self.record(instance, TYPE_SRV, CLASS_IN | CACHE_FLUSH, |w| {
+            w.u16(0);
+            w.u16(0);
+            w.u16(port);
+            w.name(host);
+        });
+    }
+
+    fn txt(&mut self, instance: &str) {
+        self.record(instance, TYPE_TXT, CLASS_IN | CACHE_FLUSH, |w| {
+            for entry in [
+                concat!("id=", env!("ID")),
+                concat!("org=", env!("ORG")),
+                concat!("topic=", crate::iot_topic!()),
+                concat!("git=", env!("GIT_HASH")),
+            ] {
+                w.bytes(&[entry.len() as u8]);
+                w.bytes(entry.as_bytes());
+            }
+        });
+    }
+
+    fn service(&mut self, service: &Service, host: &str, ip: Ipv4Address) {
+        let kind = format!("{}.local", service.kind);
+        let instance = format!("{}.{}", env!("ID"), kind);
+        self.ptr(&kind, &instance);
+        self.srv(&instance, host, service.port);
+        self.txt(&instance);
+        self.a(host, ip);
+    }
+
+    fn finish(self) -> Option<usize> {
+        if !self.ok || self.answers == 0 {
+            return None;
+        }
+        self.buf[6..8].copy_from_slice(&self.answers.to_be_bytes());
+        Some(self.len)
+    }
+}
+
+fn announce(ip: Ipv4Address, out: &mut [u8]) -> usize {
+    let host = format!("{}.local", env!("ID"));
+    let mut w = Writer::new(out, 0);
+    w.a(&host, ip);
+    for service in &services() {
+        w.service(service, &host, ip);
+    }
+    w.finish().unwrap_or(0)
+}
+
+/// Builds the response to a query, or `None` if none of its questions are about us.
+fn answer(query: &[u8], ip: Ipv4Address, out: &mut [u8]) -> Option<usize> {
+    if query.len() < 12 || query[2] & 0x80 != 0 {
+        return None; // truncated, or a response from another responder
+    }
+    let id = u16::from_be_bytes([query[0], query[1]]);
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    let host = format!("{}.local", env!("ID"));
+    let mut w = Writer::new(out, id);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        let mut name = heapless::String::<NAME_LEN>::new();
+        pos = read_name(query, pos, &mut name)?;
+        let qtype = u16::from_be_bytes([*query.get(pos)?, *query.get(pos + 1)?]);
+        pos += 4;
+
+        let any = qtype == TYPE_ANY;
+        if name.eq_ignore_ascii_case(&host) && (any || qtype == TYPE_A) {
+            w.a(&host, ip);
+        }
+        if name.eq_ignore_ascii_case(SERVICES_META) && (any || qtype == TYPE_PTR) {
+            for service in &services() {
+                w.ptr(SERVICES_META, &format!("{}.local", service.kind));
+            }
+        }
+        for service in &services() {
+            let kind = format!("{}.local", service.kind);
+            let instance = format!("{}.{}", env!("ID"), kind);
+            if name.eq_ignore_ascii_case(&kind) && (any || qtype == TYPE_PTR) {
+                w.service(service, &host, ip);
+            }
+            if name.eq_ignore_ascii_case(&instance)
+                && (any || qtype == TYPE_SRV || qtype == TYPE_TXT)
+            {
+                w.srv(&instance, &host, service.port);
+                w.txt(&instance);
+                w.a(&host, ip);
+            }
+        }
+    }
+    w.finish()
+}
+
+/// Reads a possibly compressed name at `pos` as dotted text and returns the position after it.
+fn read_name(msg: &[u8], mut pos: usize, out: &mut heapless::String<NAME_LEN>) -> Option<usize> {
+    let mut end = None;
+    // bounds the number of compression pointers followed, so a loop cannot hang us
+    for _ in 0..32 {
+        let len = *msg.get(pos)? as usize;
+        if len == 0 {
+            return Some(end.unwrap_or(pos + 1));
+        }
+        if len & 0xc0 == 0xc0 {
+            let target = (len & 0x3f) << 8 | *msg.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            pos = target;
+            continue;
+        }
+        let label = core::str::from_utf8(msg.get(pos + 1..pos + 1 + len)?).ok()?;
+        if !out.is_empty() {
+            out.push('.').ok()?;
+        }
+        out.push_str(label).ok()?;
+        pos += 1 + len;
+    }
+    None
+}
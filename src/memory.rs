@@ -0,0 +1,256 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt::Write,
+};
+
+use embassy_time::{Duration, Timer};
+use esp_alloc::EspHeap;
+use serde::Serialize;
+
+use crate::{
+    bus::{self, Channel},
+    myheap, supervisor, MYHEAP,
+};
+
+/// Seconds between two samples, unless `MEM_INTERVAL` says otherwise.
+const DEFAULT_INTERVAL_SECS: u64 = 60;
+/// Seconds over which usage that never goes down counts as a leak, unless `MEM_TREND_WINDOW`
+/// says otherwise.
+const DEFAULT_WINDOW_SECS: u64 = 3600;
+/// Samples kept for the trend, which caps the window at this many intervals.
+const MAX_SAMPLES: usize = 64;
+/// How close the largest free block estimate gets.
+const PROBE_STEP: usize = 64;
+const REPORT_LEN: usize = 512;
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Serialize)]
+struct Region {
+    size: usize,
+    used: usize,
+    free: usize,
+    /// Highest usage since boot. In PSRAM, only usage seen by a sample counts once probing
+    /// started.
+    peak: usize,
+    /// Largest single allocation that would succeed right now, for PSRAM only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    largest_free: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    uptime: u64,
+    /// The global heap in internal RAM.
+    internal: Region,
+    /// `MYHEAP`, in PSRAM.
+    psram: Region,
+    /// Allocations through `MyHeapAllocator` that failed since boot.
+    alloc_failures: u32,
+    /// Messages dropped since boot because their buffer could not be allocated, or they exceeded
+    /// the packet limits.
+    dropped_messages: u32,
+    /// Messages bridges dropped since boot because the uplink was backed up.
+    backlog_dropped: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_failure: Option<LastFailure>,
+}
+
+#[derive(Serialize)]
+struct LastFailure {
+    size: usize,
+    /// `file:line`, when known.
+    #[serde(skip_serializing_if = "str::is_empty")]
+    caller: heapless::String<64>,
+}
+
+fn last_failure() -> Option<LastFailure> {
+    let failure = myheap::last_failure()?;
+    let mut caller = heapless::String::new();
+    if let Some(location) = failure.caller {
+        write!(caller, "{}:{}", location.file(), location.line()).ok();
+    }
+    Some(LastFailure {
+        size: failure.size,
+        caller,
+    })
+}
+
+/// Finds the largest block `heap` can hand out by trying, since the allocator does not track
+/// it. Each probe is freed right away.
+fn largest_free(heap: &EspHeap) -> usize {
+    let fits = |size: usize| {
+        if size == 0 {
+            return true;
+        }
+        let Ok(layout) = Layout::from_size_align(size, 4) else {
+            return false;
+        };
+        // SAFETY: `layout` has a non-zero size, and the block is freed with the same layout
+        unsafe {
+            let ptr = heap.alloc(layout);
+            if ptr.is_null() {
+                return false;
+            }
+            heap.dealloc(ptr, layout);
+        }
+        true
+    };
+    let (mut lo, mut hi) = (0, heap.free());
+    if fits(hi) {
+        return hi;
+    }
+    while lo + PROBE_STEP < hi {
+        let mid = lo + (hi - lo) / 2;
+        if fits(mid) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+/// Reads `heap`'s usage. The largest free block is only probed in a heap given `probed_peak`:
+/// probing the internal heap would starve the radio of it for a moment. The probes also drive the
+/// allocator's high-water mark to about the heap size, so the peak of a probed heap is tracked in
+/// `probed_peak` instead, seeded before the first probe and raised by the samples since.
+fn region(heap: &EspHeap, probed_peak: Option<&mut usize>) -> Region {
+    let stats = heap.stats();
+    let used = stats.current_usage;
+    let (peak, largest_free) = match probed_peak {
+        None => (stats.max_usage, None),
+        Some(peak) => {
+            *peak = (*peak).max(used);
+            (*peak, Some(largest_free(heap)))
+        }
+    };
+    Region {
+        size: stats.size,
+        used,
+        free: stats.size - used,
+        peak,
+        largest_free,
+    }
+}
+
+/// The last few samples of one heap's usage.
+struct Trend {
+    name: &'static str,
+    samples: heapless::Deque<usize, MAX_SAMPLES>,
+    len: usize,
+}
+
+impl Trend {
+    fn new(name: &'static str, len: usize) -> Self {
+        Self {
+            name,
+            samples: heapless::Deque::new(),
+            len,
+        }
+    }
+
+    /// Adds a sample; returns the usage at the start of the window if it grew over the whole
+    /// window without ever going down.
+    fn push(&mut self, used: usize) -> Option<usize> {
+        if self.samples.len() == self.len {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(used).ok();
+        if self.samples.len() < self.len {
+            return None;
+        }
+        let mut previous = *self.samples.front()?;
+        let first = previous;
+        for &sample in self.samples.iter() {
+            if sample < previous {
+                return None;
+            }
+            previous = sample;
+        }
+        if used == first {
+            return None;
+        }
+        // warn once per window, not on every sample after it
+        self.samples.clear();
+        self.samples.push_back(used).ok();
+        Some(first)
+    }
+}
+
+fn interval() -> Duration {
+    let secs = option_env!("MEM_INTERVAL")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+    Duration::from_secs(secs.max(1))
+}
+
+fn window() -> Duration {
+    let secs = option_env!("MEM_TREND_WINDOW")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_WINDOW_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Publishes heap usage on `/memory` every `MEM_INTERVAL` seconds, and warns on `/logs` when a
+/// heap's usage went up and never down over the last `MEM_TREND_WINDOW` seconds.
+#[embassy_executor::task]
+pub async fn memory_task() {
+    let interval = interval();
+    let samples = (window().as_ticks() / interval.as_ticks()).clamp(2, MAX_SAMPLES as u64);
+    let window_secs = interval.as_secs() * samples;
+    let mut trends = [
+        Trend::new("internal", samples as usize),
+        Trend::new("psram", samples as usize),
+    ];
+    let mut payload = crate::vec_in_myheap!(0u8; REPORT_LEN);
+    let mut psram_peak = MYHEAP.stats().max_usage;
+    let heartbeat = supervisor::register("memory", None, STUCK_AFTER);
+
+    loop {
+        heartbeat.beat();
+        let report = Report {
+            uptime: embassy_time::Instant::now().as_secs(),
+            internal: region(&esp_alloc::HEAP, None),
+            psram: region(&MYHEAP, Some(&mut psram_peak)),
+            alloc_failures: myheap::alloc_failures(),
+            dropped_messages: myheap::dropped_messages(),
+            backlog_dropped: bus::dropped(),
+            last_failure: last_failure(),
+        };
+        crate::log_info!(
+            "heap: internal {}/{} (peak {}), psram {}/{} (largest free {})",
+            report.internal.used,
+            report.internal.size,
+            report.internal.peak,
+            report.psram.used,
+            report.psram.size,
+            report.psram.largest_free.unwrap_or(0)
+        );
+
+        for (trend, used) in trends
+            .iter_mut()
+            .zip([report.internal.used, report.psram.used])
+        {
+            if let Some(first) = trend.push(used) {
+                crate::log_warn!(
+                    "{} heap grew from {} to {} bytes over {} s, possible leak",
+                    trend.name,
+                    first,
+                    used,
+                    window_secs
+                );
+            }
+        }
+
+        match serde_json_core::to_slice(&report, &mut payload[..]) {
+            Ok(len) => {
+                // waits while the uplink is down
+                heartbeat.idle();
+                bus::send(Channel::Memory, &payload[..len]).await;
+            }
+            Err(_) => crate::log_error!("memory report does not fit"),
+        }
+        heartbeat.idle();
+        Timer::after(interval).await;
+    }
+}
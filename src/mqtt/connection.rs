@@ -1,10 +1,10 @@
 use core::convert::TryInto;
 
 use embassy_net::{tcp::TcpSocket, IpEndpoint, Stack};
-use embassy_time::Timer;
+use embassy_time::{with_timeout, Duration, Timer};
 use mountain_mqtt::client::{Client, ClientNoQueue, Delay};
 
-use crate::{iot_topic, led};
+use crate::{iot_topic, led, settings};
 
 use super::inbound::{InboundEventHandler, MAX_APPLICATION_PROPERTIES};
 
@@ -14,6 +14,8 @@ pub const MQTT_BUFFER_SIZE: usize = 4096;
 pub const CLIENT_TIMEOUT_MS: u32 = 5000;
 const DNS_HOST: &str = "ssca.desrochers.space";
 const MQTT_PORT: u16 = 1883;
+/// Bounds the DNS query and the TCP handshake each, well inside the task's heartbeat timeout.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub type ClientType<'a> = ClientNoQueue<
     'a,
@@ -30,26 +32,42 @@ pub(super) async fn setup_client<'a>(
     mqtt_buffer: &'a mut [u8],
 ) -> Option<ClientType<'a>> {
     let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
-    let addr = stack
-        .dns_query(DNS_HOST, smoltcp::wire::DnsQueryType::A)
-        .await;
-    if let Err(e) = addr {
-        defmt::error!("dns query {:?}", defmt::Debug2Format(&e));
-        Timer::after_millis(500).await;
-        led::state(led::LedState::MQTT(false)).await;
-        return None;
-    }
-    let result_connection = socket
-        .connect(IpEndpoint::new(
-            addr.unwrap().first().unwrap().clone(),
-            MQTT_PORT,
-        ))
-        .await;
-    if let Err(e) = result_connection {
-        defmt::error!("socket connect {:?}", defmt::Debug2Format(&e));
-        Timer::after_millis(500).await;
-        led::state(led::LedState::MQTT(false)).await;
-        return None;
+    // a broker entered on the setup page replaces the built-in one
+    let (host, port) = match settings::get().broker {
+        Some(broker) => (broker.host, broker.port),
+        None => (heapless::String::try_from(DNS_HOST).unwrap(), MQTT_PORT),
+    };
+    let addr = with_timeout(
+        CONNECT_TIMEOUT,
+        stack.dns_query(&host, smoltcp::wire::DnsQueryType::A),
+    )
+    .await;
+    let addr = match addr {
+        Ok(Ok(addr)) => addr,
+        Ok(Err(e)) => {
+            crate::log_error!("dns query {:?}", e);
+            return disconnected().await;
+        }
+        Err(_) => {
+            crate::log_error!("dns query timed out");
+            return disconnected().await;
+        }
+    };
+    let result_connection = with_timeout(
+        CONNECT_TIMEOUT,
+        socket.connect(IpEndpoint::new(addr.first().unwrap().clone(), port)),
+    )
+    .await;
+    match result_connection {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            crate::log_error!("socket connect {:?}", e);
+            return disconnected().await;
+        }
+        Err(_) => {
+            crate::log_error!("socket connect timed out");
+            return disconnected().await;
+        }
     }
     let connection = mountain_mqtt::embedded_io_async::ConnectionEmbedded::new(socket);
     Some(ClientNoQueue::new(
@@ -61,9 +79,22 @@ pub(super) async fn setup_client<'a>(
     ))
 }
 
+/// Backs off before the caller retries and shows the broker as unreachable.
+async fn disconnected<T>() -> Option<T> {
+    Timer::after_millis(500).await;
+    led::state(led::LedState::MQTT(false)).await;
+    None
+}
+
 pub(super) async fn setup_subscriptions<'a>(client: &mut ClientType<'a>) {
     let topics = [
         concat!(iot_topic!(), "/rpc/tcp"),
+        concat!(iot_topic!(), "/rpc/tcp/+"),
+        concat!(iot_topic!(), "/rpc/uart"),
+        concat!(iot_topic!(), "/rpc/udp"),
+        concat!(iot_topic!(), "/rpc/wifi"),
+        concat!(iot_topic!(), "/rpc/ota"),
+        concat!(iot_topic!(), "/rpc/log"),
         concat!(iot_topic!(), "/ctrl"),
         concat!(iot_topic!(), "/echo"),
     ];
@@ -75,7 +106,7 @@ pub(super) async fn setup_subscriptions<'a>(client: &mut ClientType<'a>) {
             )
             .await;
         if let Err(e) = result {
-            defmt::error!("{:?}", defmt::Debug2Format(&e));
+            crate::log_error!("{:?}", e);
             led::state(led::LedState::RPCError).await;
         }
     }
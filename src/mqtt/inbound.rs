@@ -1,13 +1,17 @@
 use mountain_mqtt::client::{ClientReceivedEvent, EventHandler, EventHandlerError};
 
 use crate::{
+    bus::{self, Channel},
     iot_topic,
+    logger,
+    ota,
     output,
     tcp,
+    uart,
+    udp,
+    wifi,
 };
 
-use super::publish::mqtt_send;
-
 pub(super) const MAX_APPLICATION_PROPERTIES: usize = 16;
 
 pub(super) struct InboundEventHandler;
@@ -24,12 +28,34 @@ impl EventHandler<MAX_APPLICATION_PROPERTIES> for InboundEventHandler {
                         output::output_state_from_mqtt(message).await?;
                     }
                     concat!(iot_topic!(), "/rpc/tcp") => {
-                        tcp::tcp_send(message.payload).await;
+                        tcp::tcp_send(None, message.payload);
+                    }
+                    concat!(iot_topic!(), "/rpc/uart") => {
+                        uart::uart_transaction_from_mqtt(message.payload)?;
+                    }
+                    concat!(iot_topic!(), "/rpc/udp") => {
+                        udp::udp_send(message.payload);
+                    }
+                    concat!(iot_topic!(), "/rpc/wifi") => {
+                        wifi::wifi_cmd_from_mqtt(message.payload).await;
+                    }
+                    concat!(iot_topic!(), "/rpc/ota") => {
+                        ota::ota_cmd_from_mqtt(message.payload);
+                    }
+                    concat!(iot_topic!(), "/rpc/log") => {
+                        logger::log_cmd_from_mqtt(message.payload);
                     }
                     concat!(iot_topic!(), "/echo") => {
-                        mqtt_send(message.payload, "/echo").await;
+                        bus::send(Channel::Echo, message.payload).await;
+                    }
+                    topic => {
+                        let slot = topic
+                            .strip_prefix(concat!(iot_topic!(), "/rpc/tcp/"))
+                            .and_then(|slot| slot.parse().ok());
+                        if let Some(slot) = slot {
+                            tcp::tcp_send(Some(slot), message.payload);
+                        }
                     }
-                    _ => {}
                 }
                 Ok(())
             }
@@ -38,7 +64,7 @@ impl EventHandler<MAX_APPLICATION_PROPERTIES> for InboundEventHandler {
                 granted_qos,
                 maximum_qos,
             } => {
-                defmt::warn!(
+                crate::log_warn!(
                     "subscription granted at {} (requested {})",
                     granted_qos as u8,
                     maximum_qos as u8
@@ -46,11 +72,11 @@ impl EventHandler<MAX_APPLICATION_PROPERTIES> for InboundEventHandler {
                 Ok(())
             }
             ClientReceivedEvent::PublishedMessageHadNoMatchingSubscribers => {
-                defmt::warn!("published message had no subscribers");
+                crate::log_warn!("published message had no subscribers");
                 Ok(())
             }
             ClientReceivedEvent::NoSubscriptionExisted => {
-                defmt::warn!("unsubscribe ack reported no existing subscription");
+                crate::log_warn!("unsubscribe ack reported no existing subscription");
                 Ok(())
             }
         }
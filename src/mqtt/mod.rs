@@ -1,7 +1,9 @@
-pub use publish::{mqtt_send, MQTT_PACKET_LEN};
-pub use task::mqtt_task;
+pub use publish::{Mqtt, MQTT_PACKET_LEN};
+pub use task::{connected, mqtt_task};
+pub use webhook::{webhook_enabled, webhook_task, Webhook};
 
 mod connection;
 mod inbound;
 mod publish;
 mod task;
+mod webhook;
@@ -1,8 +1,12 @@
-use defmt::info;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel as Queue};
 use heapless::String;
 
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
-use crate::MyHeapVec;
+use crate::{
+    bus::{Channel, Transport},
+    myheap, MyHeapVec,
+};
+
+use super::webhook_enabled;
 
 pub const MQTT_PACKET_LEN: usize = 1024;
 
@@ -12,25 +16,90 @@ pub struct PublishPacket {
     pub len: usize,
 }
 
-static WRITE: Channel<CriticalSectionRawMutex, PublishPacket, 8> = Channel::new();
+#[derive(Debug, defmt::Format)]
+enum PacketError {
+    TopicTooLong(usize),
+    PayloadTooLong(usize),
+    OutOfMemory,
+}
+
+/// Copies the payload to PSRAM. Whatever fails is counted as dropped; only oversize messages are
+/// logged, since they point at a bug rather than a busy device.
+fn packet(buf: &[u8], topic: &str) -> Result<PublishPacket, PacketError> {
+    let result = build(buf, topic);
+    match &result {
+        Err(PacketError::OutOfMemory) => myheap::count_dropped(),
+        Err(e) => {
+            myheap::count_dropped();
+            crate::log_error!("publish on {} dropped: {:?}", topic, e);
+        }
+        Ok(_) => {}
+    }
+    result
+}
 
-/// Enqueue a packet for MQTT publishing. Panics if topic or payload exceed limits.
-pub async fn mqtt_send(buf: &[u8], topic: &str) {
-    info!("MQTT publish channel free capacity: {}", WRITE.free_capacity());
-    let topic = String::try_from(topic).expect("Topic too big");
+fn build(buf: &[u8], topic: &str) -> Result<PublishPacket, PacketError> {
+    let topic = String::try_from(topic).map_err(|_| PacketError::TopicTooLong(topic.len()))?;
     let len = buf.len();
-    assert!(len < MQTT_PACKET_LEN, "Packet too big");
-    let mut heap_buf = crate::vec_in_myheap!(0u8; len);
+    if len >= MQTT_PACKET_LEN {
+        return Err(PacketError::PayloadTooLong(len));
+    }
+    let mut heap_buf = crate::try_vec_in_myheap!(0u8; len).ok_or(PacketError::OutOfMemory)?;
     heap_buf.copy_from_slice(&buf[..len]);
-    WRITE
-        .send(PublishPacket {
-            topic,
-            buf: heap_buf,
-            len,
-        })
-        .await;
+    Ok(PublishPacket {
+        topic,
+        buf: heap_buf,
+        len,
+    })
+}
+
+/// Packets waiting for the task of one transport.
+pub(super) struct Outbox(Queue<CriticalSectionRawMutex, PublishPacket, 8>);
+
+impl Outbox {
+    pub(super) const fn new() -> Self {
+        Self(Queue::new())
+    }
+
+    /// Enqueues a packet, waiting for room. Drops it when the topic or payload exceed limits or
+    /// PSRAM is full.
+    pub(super) async fn send(&self, channel: Channel, payload: &[u8]) {
+        // defmt only: a logged record is published too, and would log again
+        defmt::debug!("publish queue free capacity: {}", self.0.free_capacity());
+        if let Ok(packet) = packet(payload, &channel.topic()) {
+            self.0.send(packet).await;
+        }
+    }
+
+    /// Like `send`, but drops the packet instead of waiting when the queue is full, for callers
+    /// that must keep running while the uplink is down. Returns whether it was queued.
+    pub(super) fn try_send(&self, channel: Channel, payload: &[u8]) -> bool {
+        packet(payload, &channel.topic()).is_ok_and(|packet| self.0.try_send(packet).is_ok())
+    }
+
+    pub(super) async fn next(&self) -> PublishPacket {
+        self.0.receive().await
+    }
+}
+
+static OUTBOX: Outbox = Outbox::new();
+
+/// Messages for the broker, drained by `mqtt_task`. Takes nothing when the webhook is used
+/// instead.
+pub struct Mqtt;
+
+impl Transport for Mqtt {
+    async fn send(&self, channel: Channel, payload: &[u8]) {
+        if !webhook_enabled() {
+            OUTBOX.send(channel, payload).await;
+        }
+    }
+
+    fn try_send(&self, channel: Channel, payload: &[u8]) -> bool {
+        !webhook_enabled() && OUTBOX.try_send(channel, payload)
+    }
 }
 
 pub(super) async fn next_publish() -> PublishPacket {
-    WRITE.receive().await
+    OUTBOX.next().await
 }
@@ -1,8 +1,11 @@
-use core::str::FromStr;
+use core::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use embassy_futures::select::select3;
+use embassy_futures::select::{select, select3};
 use embassy_net::Stack;
-use embassy_time::Timer;
+use embassy_time::{Duration, Timer};
 use heapless::Vec;
 use mountain_mqtt::{
     client::{Client, ConnectionSettings},
@@ -11,7 +14,10 @@ use mountain_mqtt::{
 };
 use serde::Serialize;
 
-use crate::{iot_topic, led};
+use crate::{
+    boot, iot_topic, led, link,
+    supervisor::{self, Heartbeat},
+};
 
 use super::{
     connection::{alloc_buffers, setup_client, setup_subscriptions},
@@ -20,18 +26,77 @@ use super::{
 
 const CONNECTION_PAYLOAD_SIZE: usize = 256;
 const PING_INTERVAL_SECS: u64 = 5;
+/// Longest a connection attempt or a publish may take before the task counts as stuck.
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether we currently hold a broker session.
+pub fn connected() -> bool {
+    CONNECTED.load(Ordering::Relaxed)
+}
 
 #[derive(Debug, Serialize)]
 struct ConnectionPacket {
     msg: heapless::String<64>,
     last_will: bool,
+    /// Why this boot happened.
+    reset: boot::ResetCause,
+    boots: u32,
+    /// Abnormal resets in a row; climbing means the device is flapping.
+    abnormal_boots: u32,
 }
 
+impl ConnectionPacket {
+    fn new(msg: &str, last_will: bool) -> Self {
+        let boot = boot::info();
+        Self {
+            msg: heapless::String::from_str(msg).unwrap(),
+            last_will,
+            reset: boot.cause,
+            boots: boot.boots,
+            abnormal_boots: boot.abnormal,
+        }
+    }
+}
+
+/// Keeps a broker connection up over the active uplink, reconnecting when it switches.
 #[embassy_executor::task]
-pub async fn mqtt_task(stack: Stack<'static>) -> ! {
+pub async fn mqtt_task() -> ! {
     let (rx_buffer, tx_buffer, mqtt_buffer) = alloc_buffers();
+    let mut uplink = link::receiver();
+    let heartbeat = supervisor::register("mqtt", None, STUCK_AFTER);
+
+    loop {
+        heartbeat.idle();
+        let stack = uplink.get().await.stack;
+        select(
+            run(
+                stack,
+                &mut rx_buffer[..],
+                &mut tx_buffer[..],
+                &mut mqtt_buffer[..],
+                &heartbeat,
+            ),
+            uplink.changed(),
+        )
+        .await;
+        CONNECTED.store(false, Ordering::Relaxed);
+        crate::log_info!("uplink changed, reconnecting mqtt");
+        led::state(led::LedState::MQTT(false)).await;
+    }
+}
 
+async fn run(
+    stack: Stack<'static>,
+    rx_buffer: &mut [u8],
+    tx_buffer: &mut [u8],
+    mqtt_buffer: &mut [u8],
+    heartbeat: &Heartbeat,
+) -> ! {
     'main: loop {
+        heartbeat.beat();
+        CONNECTED.store(false, Ordering::Relaxed);
         let mut client = match setup_client(
             stack,
             &mut rx_buffer[..],
@@ -46,10 +111,7 @@ pub async fn mqtt_task(stack: Stack<'static>) -> ! {
 
         let mut will_payload = crate::vec_in_myheap!(0u8; CONNECTION_PAYLOAD_SIZE);
         let will_payload_len = serde_json_core::to_slice(
-            &ConnectionPacket {
-                last_will: true,
-                msg: heapless::String::from_str("me dead").unwrap(),
-            },
+            &ConnectionPacket::new("me dead", true),
             &mut will_payload[..],
         )
         .unwrap();
@@ -68,21 +130,16 @@ pub async fn mqtt_task(stack: Stack<'static>) -> ! {
             )
             .await;
         if let Err(e) = result_connection {
-            defmt::error!("connect mqtt {:?}", defmt::Debug2Format(&e));
+            crate::log_error!("connect mqtt {:?}", e);
             Timer::after_millis(500).await;
             led::state(led::LedState::MQTT(false)).await;
             continue 'main;
         }
 
         let mut payload = crate::vec_in_myheap!(0u8; CONNECTION_PAYLOAD_SIZE);
-        let payload_len = serde_json_core::to_slice(
-            &ConnectionPacket {
-                last_will: false,
-                msg: heapless::String::from_str("me alive").unwrap(),
-            },
-            &mut payload[..],
-        )
-        .unwrap();
+        let payload_len =
+            serde_json_core::to_slice(&ConnectionPacket::new("me alive", false), &mut payload[..])
+                .unwrap();
         client
             .publish(
                 concat!(iot_topic!(), "/connection"),
@@ -94,18 +151,12 @@ pub async fn mqtt_task(stack: Stack<'static>) -> ! {
             .ok();
 
         setup_subscriptions(&mut client).await;
+        CONNECTED.store(true, Ordering::Relaxed);
         led::state(led::LedState::MQTT(true)).await;
-        client
-            .publish(
-                concat!(iot_topic!(), "/logs"),
-                b"Connected!",
-                QualityOfService::Qos0,
-                false,
-            )
-            .await
-            .ok();
+        crate::log_info!("Connected!");
 
         loop {
+            heartbeat.beat();
             match select3(
                 next_publish_packet(),
                 client.poll(true),
@@ -115,7 +166,8 @@ pub async fn mqtt_task(stack: Stack<'static>) -> ! {
             {
                 embassy_futures::select::Either3::First(packet) => {
                     if let Ok(ascii) = core::str::from_utf8(&packet.buf[..packet.len]) {
-                        defmt::debug!("{}", ascii);
+                        // defmt only: a logged record is published too, and would log again
+                        defmt::debug!("{=str}", ascii);
                     }
                     let r = client
                         .publish(
@@ -126,7 +178,7 @@ pub async fn mqtt_task(stack: Stack<'static>) -> ! {
                         )
                         .await;
                     if let Err(e) = r {
-                        defmt::error!("publish mqtt {:?}", defmt::Debug2Format(&e));
+                        crate::log_error!("publish mqtt {:?}", e);
                         Timer::after_millis(500).await;
                         led::state(led::LedState::MQTT(false)).await;
                         continue 'main;
@@ -137,14 +189,14 @@ pub async fn mqtt_task(stack: Stack<'static>) -> ! {
                     Timer::after_millis(10).await;
                 }
                 embassy_futures::select::Either3::Second(Err(e)) => {
-                    defmt::error!("poll mqtt {:?}", defmt::Debug2Format(&e));
+                    crate::log_error!("poll mqtt {:?}", e);
                     led::state(led::LedState::MQTT(false)).await;
                     Timer::after_millis(500).await;
                     continue 'main;
                 }
                 embassy_futures::select::Either3::Third(_) => {
                     if let Err(e) = client.send_ping().await {
-                        defmt::error!("{:?}", defmt::Debug2Format(&e));
+                        crate::log_error!("{:?}", e);
                         led::state(led::LedState::RPCError).await;
                     }
                 }
@@ -0,0 +1,262 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use embassy_net::{
+    dns::DnsSocket,
+    tcp::client::{TcpClient, TcpClientState},
+};
+use embassy_time::{with_deadline, with_timeout, Duration, Instant, Timer};
+use esp_hal::rng::Rng;
+use hmac::{Hmac, Mac};
+use reqwless::{
+    client::{HttpClient, TlsConfig, TlsVerify},
+    headers::ContentType,
+    request::{Method, RequestBuilder},
+};
+use sha2::Sha256;
+
+use crate::{
+    bus::{Channel, Transport},
+    led, link, myheap, supervisor, MyHeapAllocator, MyHeapVec, MYHEAP,
+};
+
+use super::publish::{Outbox, PublishPacket, MQTT_PACKET_LEN};
+
+/// Messages are POSTed here instead of going to a broker when it is set.
+const WEBHOOK_URL: Option<&str> = option_env!("WEBHOOK_URL");
+/// Key of the `X-Signature` header, shared with the receiver. The server's certificate is not
+/// verified, so the secret itself never goes on the wire.
+///
+/// The device has no wall clock, so the signature covers `X-Boot`, random per boot, and
+/// `X-Timestamp`, milliseconds since then, as `<boot>.<timestamp>.<body>`. A receiver that
+/// only accepts timestamps above the last one it saw for a boot rejects replayed requests.
+const WEBHOOK_SECRET: Option<&str> = option_env!("WEBHOOK_SECRET");
+/// How long the first message of a batch waits for company.
+const BATCH_WINDOW: Duration = Duration::from_secs(2);
+const MAX_BATCH: usize = 16;
+/// A message never takes more than this in the body: an escaped control character takes six
+/// bytes, `\u00XX`, hex two per byte, and the topic and keys fit in the rest.
+const MAX_ENTRY_LEN: usize = 6 * MQTT_PACKET_LEN + 128;
+const MAX_BODY_LEN: usize = 16 * 1024;
+const RX_LEN: usize = 2048;
+const TLS_READ_LEN: usize = 16640;
+const TLS_WRITE_LEN: usize = 4096;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// Attempts per batch before it is dropped.
+const MAX_ATTEMPTS: u32 = 4;
+/// Longer than a request and the backoff after it.
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+/// Whether messages go to `WEBHOOK_URL` rather than the MQTT broker.
+pub fn webhook_enabled() -> bool {
+    WEBHOOK_URL.is_some()
+}
+
+static OUTBOX: Outbox = Outbox::new();
+
+/// Messages for `WEBHOOK_URL`, drained by `webhook_task`. Takes nothing unless it is set.
+pub struct Webhook;
+
+impl Transport for Webhook {
+    async fn send(&self, channel: Channel, payload: &[u8]) {
+        if webhook_enabled() {
+            OUTBOX.send(channel, payload).await;
+        }
+    }
+
+    fn try_send(&self, channel: Channel, payload: &[u8]) -> bool {
+        webhook_enabled() && OUTBOX.try_send(channel, payload)
+    }
+}
+
+struct Buffers {
+    rx: MyHeapVec<u8>,
+    tls_read: MyHeapVec<u8>,
+    tls_write: MyHeapVec<u8>,
+}
+
+/// `{"id":"<ID>","uptime":<s>,"messages":[{"topic":"...","text":"..."},{"topic":"...","hex":"..."}]}`.
+/// Payloads that are valid UTF-8 go in `text`, anything else in `hex`.
+struct Batch {
+    body: MyHeapVec<u8>,
+    count: usize,
+}
+
+impl Write for Batch {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.body.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl Batch {
+    /// `None` when PSRAM has no room for the body.
+    fn new() -> Option<Self> {
+        let mut body = Vec::new_in(MyHeapAllocator(&MYHEAP));
+        body.try_reserve_exact(MAX_BODY_LEN).ok()?;
+        let mut batch = Self { body, count: 0 };
+        write!(
+            batch,
+            r#"{{"id":"{}","uptime":{},"messages":["#,
+            env!("ID"),
+            Instant::now().as_secs()
+        )
+        .ok();
+        Some(batch)
+    }
+
+    fn has_room(&self) -> bool {
+        self.count < MAX_BATCH && self.body.len() + MAX_ENTRY_LEN < MAX_BODY_LEN
+    }
+
+    fn push(&mut self, packet: &PublishPacket) {
+        if self.count > 0 {
+            self.body.push(b',');
+        }
+        self.count += 1;
+        let payload = &packet.buf[..packet.len];
+        write!(self, r#"{{"topic":"{}","#, packet.topic).ok();
+        match core::str::from_utf8(payload) {
+            Ok(text) => {
+                self.body.extend_from_slice(br#""text":""#);
+                for c in text.chars() {
+                    match c {
+                        '"' => self.write_str("\\\"").ok(),
+                        '\\' => self.write_str("\\\\").ok(),
+                        c if (c as u32) < 0x20 => write!(self, "\\u{:04x}", c as u32).ok(),
+                        c => self.write_char(c).ok(),
+                    };
+                }
+            }
+            Err(_) => {
+                self.body.extend_from_slice(br#""hex":""#);
+                for b in payload {
+                    write!(self, "{:02x}", b).ok();
+                }
+            }
+        }
+        self.body.extend_from_slice(b"\"}");
+    }
+
+    fn finish(mut self) -> MyHeapVec<u8> {
+        self.body.extend_from_slice(b"]}");
+        self.body
+    }
+}
+
+/// `sha256=<hex>`, the HMAC-SHA256 of `<boot>.<timestamp>.<body>` keyed with `secret`.
+fn signature(secret: &str, boot: &str, timestamp: &str, body: &[u8]) -> heapless::String<71> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    for part in [boot.as_bytes(), b".", timestamp.as_bytes(), b".", body] {
+        mac.update(part);
+    }
+    let mut header = heapless::String::new();
+    header.push_str("sha256=").ok();
+    for b in mac.finalize().into_bytes() {
+        write!(header, "{:02x}", b).ok();
+    }
+    header
+}
+
+async fn post(url: &str, boot: &str, body: &[u8], bufs: &mut Buffers) -> Result<(), ()> {
+    let stack = link::active().ok_or(())?.stack;
+    let state = TcpClientState::<1, 1024, 1024>::new();
+    let tcp = TcpClient::new(stack, &state);
+    let dns = DnsSocket::new(stack);
+    let rng = Rng::new();
+    let tls = TlsConfig::new(
+        (rng.random() as u64) << 32 | rng.random() as u64,
+        &mut bufs.tls_read[..],
+        &mut bufs.tls_write[..],
+        TlsVerify::None,
+    );
+    let mut client = HttpClient::new_with_tls(&tcp, &dns, tls);
+    let mut timestamp = heapless::String::<20>::new();
+    write!(timestamp, "{}", Instant::now().as_millis()).ok();
+    let signature = WEBHOOK_SECRET.map(|secret| signature(secret, boot, &timestamp, body));
+    let signed = signature.as_ref().map(|s| {
+        [
+            ("X-Boot", boot),
+            ("X-Timestamp", timestamp.as_str()),
+            ("X-Signature", s.as_str()),
+        ]
+    });
+
+    let send = async {
+        let request = client
+            .request(Method::POST, url)
+            .await
+            .map_err(|e| crate::log_warn!("webhook {:?}", e))?;
+        let mut request = request
+            .body(body)
+            .content_type(ContentType::ApplicationJson)
+            .headers(signed.as_ref().map(|h| &h[..]).unwrap_or(&[]));
+        let response = request
+            .send(&mut bufs.rx[..])
+            .await
+            .map_err(|e| crate::log_warn!("webhook {:?}", e))?;
+        if !response.status.is_successful() {
+            crate::log_warn!("webhook status {:?}", response.status);
+            return Err(());
+        }
+        Ok(())
+    };
+    with_timeout(REQUEST_TIMEOUT, send).await.map_err(|_| ())?
+}
+
+/// Delivers what producers hand to `bus::send` as batched JSON POSTs to `WEBHOOK_URL`. Runs
+/// instead of `mqtt_task`, so there is no way to send commands to the device in this mode.
+#[embassy_executor::task]
+pub async fn webhook_task() -> ! {
+    let url = WEBHOOK_URL.expect("webhook_task needs WEBHOOK_URL");
+    let mut bufs = Buffers {
+        rx: crate::vec_in_myheap!(0u8; RX_LEN),
+        tls_read: crate::vec_in_myheap!(0u8; TLS_READ_LEN),
+        tls_write: crate::vec_in_myheap!(0u8; TLS_WRITE_LEN),
+    };
+    let mut boot = heapless::String::<8>::new();
+    write!(boot, "{:08x}", Rng::new().random()).ok();
+    let mut dropped: u32 = 0;
+    let heartbeat = supervisor::register("webhook", None, STUCK_AFTER);
+    crate::log_info!("publishing to webhook {}", url);
+
+    loop {
+        heartbeat.idle();
+        let first = OUTBOX.next().await;
+        heartbeat.beat();
+        let Some(mut batch) = Batch::new() else {
+            myheap::count_dropped();
+            crate::log_warn!("webhook: no room for a batch, message dropped");
+            continue;
+        };
+        batch.push(&first);
+        drop(first);
+        let deadline = Instant::now() + BATCH_WINDOW;
+        while batch.has_room() {
+            match with_deadline(deadline, OUTBOX.next()).await {
+                Ok(packet) => batch.push(&packet),
+                Err(_) => break,
+            }
+        }
+        let count = batch.count;
+        let body = batch.finish();
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            heartbeat.beat();
+            if post(url, &boot, &body, &mut bufs).await.is_ok() {
+                led::state(led::LedState::MQTT(true)).await;
+                break;
+            }
+            led::state(led::LedState::MQTT(false)).await;
+            if attempt == MAX_ATTEMPTS {
+                dropped += count as u32;
+                crate::log_error!("webhook: dropped {} messages ({} so far)", count, dropped);
+                break;
+            }
+            Timer::after_secs(1 << attempt).await;
+        }
+    }
+}
@@ -1,9 +1,13 @@
 use core::{
     alloc::{Allocator, GlobalAlloc, Layout},
+    cell::Cell,
+    panic::Location,
     ptr::NonNull,
+    sync::atomic::{AtomicU32, Ordering},
 };
 
 use alloc::alloc::AllocError;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
 use esp_alloc::EspHeap;
 
 pub static MYHEAP: EspHeap = EspHeap::empty();
@@ -13,6 +17,73 @@ pub struct MyHeapAllocator<'a>(pub &'a EspHeap);
 
 pub type MyHeapVec<T> = alloc::vec::Vec<T, MyHeapAllocator<'static>>;
 
+/// Allocations through `MyHeapAllocator` that found no room.
+static ALLOC_FAILURES: AtomicU32 = AtomicU32::new(0);
+/// Messages dropped because there was no room to queue them.
+static DROPPED_MESSAGES: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy)]
+pub struct AllocFailure {
+    pub size: usize,
+    /// Known when the allocation went through `try_vec_in_myheap!`.
+    pub caller: Option<&'static Location<'static>>,
+}
+
+static LAST_FAILURE: Mutex<CriticalSectionRawMutex, Cell<Option<AllocFailure>>> =
+    Mutex::new(Cell::new(None));
+
+pub fn alloc_failures() -> u32 {
+    ALLOC_FAILURES.load(Ordering::Relaxed)
+}
+
+pub fn last_failure() -> Option<AllocFailure> {
+    LAST_FAILURE.lock(|f| f.get())
+}
+
+pub fn dropped_messages() -> u32 {
+    DROPPED_MESSAGES.load(Ordering::Relaxed)
+}
+
+/// For message paths that give up on a message rather than abort when the heap is full.
+pub fn count_dropped() {
+    DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+}
+
+fn failed(size: usize) -> AllocError {
+    ALLOC_FAILURES.fetch_add(1, Ordering::Relaxed);
+    LAST_FAILURE.lock(|f| f.set(Some(AllocFailure { size, caller: None })));
+    AllocError
+}
+
+/// Called when `try_vec_in_myheap!` comes back empty handed, to put a name on the failure.
+fn out_of_memory(size: usize, caller: &'static Location<'static>) {
+    // defmt only: forwarding a record to `/logs` allocates, and would fail the same way
+    defmt::warn!(
+        "out of memory: {} bytes at {=str}:{}",
+        size,
+        caller.file(),
+        caller.line()
+    );
+    LAST_FAILURE.lock(|f| {
+        f.set(Some(AllocFailure {
+            size,
+            caller: Some(caller),
+        }))
+    });
+}
+
+/// Backs `try_vec_in_myheap!`.
+#[track_caller]
+pub fn try_vec<T: Clone>(value: T, len: usize) -> Option<MyHeapVec<T>> {
+    let mut v = MyHeapVec::new_in(MyHeapAllocator(&MYHEAP));
+    if v.try_reserve_exact(len).is_err() {
+        out_of_memory(len * core::mem::size_of::<T>(), Location::caller());
+        return None;
+    }
+    v.resize(len, value);
+    Some(v)
+}
+
 fn empty_slice() -> NonNull<[u8]> {
     NonNull::slice_from_raw_parts(NonNull::<u8>::dangling(), 0)
 }
@@ -24,7 +95,7 @@ fn slice_from_raw(ptr: *mut u8, size: usize) -> Result<NonNull<[u8]>, AllocError
 
     NonNull::new(ptr)
         .map(|p| NonNull::slice_from_raw_parts(p, size))
-        .ok_or(AllocError)
+        .ok_or_else(|| failed(size))
 }
 
 #[macro_export]
@@ -36,6 +107,15 @@ macro_rules! vec_in_myheap {
     }};
 }
 
+/// Like `vec_in_myheap!`, but `None` instead of aborting when there is no room, for buffers
+/// allocated per message. The failure is recorded with the caller's location.
+#[macro_export]
+macro_rules! try_vec_in_myheap {
+    ($value:expr; $len:expr) => {
+        $crate::myheap::try_vec($value, $len)
+    };
+}
+
 unsafe impl<'a> Allocator for MyHeapAllocator<'a> {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         if layout.size() == 0 {
@@ -95,7 +175,7 @@ unsafe impl<'a> Allocator for MyHeapAllocator<'a> {
             <EspHeap as GlobalAlloc>::realloc(self.0, ptr.as_ptr(), layout, new_layout.size())
         };
         if raw.is_null() {
-            return Err(AllocError);
+            return Err(failed(new_layout.size()));
         }
 
         let old_size = layout.size();
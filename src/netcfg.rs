@@ -0,0 +1,121 @@
+use core::str::FromStr;
+
+use embassy_net::{Config, ConfigV4, DhcpConfig, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_time::{Duration, WithTimeout};
+
+/// Sockets open at once on the uplink stack: DHCP and DNS, the TCP slots, MQTT or the webhook,
+/// the HTTP server, the UDP bridge, discovery, mDNS and the OTA download.
+pub const SOCKETS: usize = 2 + crate::tcp::TCP_MAX_CLIENTS + 1 + crate::http::HTTP_TASKS + 4;
+
+/// How long DHCP gets before a configured static address is used instead.
+const DHCP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// IPv4 settings of one interface, from the `<ETH|WIFI>_{IP,GW,DNS,DHCP}` and `HOSTNAME` build
+/// variables.
+///
+/// With `_IP` unset the interface uses DHCP. With `_IP` set and `_DHCP=0` it is static. With both
+/// it tries DHCP first and falls back to the static address after `DHCP_TIMEOUT`.
+#[derive(Debug, Clone)]
+pub struct IfaceConfig {
+    pub dhcp: bool,
+    pub fixed: Option<StaticConfigV4>,
+    pub hostname: Option<&'static str>,
+}
+
+macro_rules! iface_config {
+    ($prefix:literal) => {
+        IfaceConfig::parse(
+            $prefix,
+            option_env!(concat!($prefix, "_IP")),
+            option_env!(concat!($prefix, "_GW")),
+            option_env!(concat!($prefix, "_DNS")),
+            option_env!(concat!($prefix, "_DHCP")),
+        )
+    };
+}
+
+pub fn ethernet() -> IfaceConfig {
+    iface_config!("ETH")
+}
+
+pub fn wifi() -> IfaceConfig {
+    iface_config!("WIFI")
+}
+
+impl IfaceConfig {
+    fn parse(
+        name: &str,
+        ip: Option<&str>,
+        gateway: Option<&str>,
+        dns: Option<&str>,
+        dhcp: Option<&str>,
+    ) -> Self {
+        let fixed = ip.and_then(|ip| {
+            let (addr, prefix) = ip.split_once('/').unwrap_or((ip, "24"));
+            let address = Ipv4Cidr::new(
+                Ipv4Address::from_str(addr).ok()?,
+                prefix.parse().ok().filter(|p| *p <= 32)?,
+            );
+            let mut dns_servers = heapless::Vec::new();
+            for server in dns.unwrap_or("").split(',').filter(|s| !s.is_empty()) {
+                match Ipv4Address::from_str(server.trim()) {
+                    Ok(server) => {
+                        dns_servers.push(server).ok();
+                    }
+                    Err(_) => crate::log_error!("{}: invalid dns server {}", name, server),
+                }
+            }
+            Some(StaticConfigV4 {
+                address,
+                gateway: gateway.and_then(|gw| Ipv4Address::from_str(gw).ok()),
+                dns_servers,
+            })
+        });
+        if ip.is_some() && fixed.is_none() {
+            crate::log_error!("{}: invalid static address {}, using DHCP", name, ip);
+        }
+
+        Self {
+            dhcp: fixed.is_none() || !matches!(dhcp, Some("0" | "false" | "no")),
+            fixed,
+            hostname: Some(option_env!("HOSTNAME").unwrap_or(env!("ID"))),
+        }
+    }
+
+    /// The configuration the stack starts with.
+    pub fn initial(&self) -> Config {
+        if !self.dhcp {
+            if let Some(fixed) = &self.fixed {
+                return Config::ipv4_static(fixed.clone());
+            }
+        }
+        let mut dhcp = DhcpConfig::default();
+        dhcp.hostname = self
+            .hostname
+            .and_then(|h| heapless::String::try_from(h).ok());
+        Config::dhcpv4(dhcp)
+    }
+}
+
+/// Switches `stack` to the static address if DHCP has not produced one within `DHCP_TIMEOUT`.
+/// The fallback lasts until the next reboot.
+#[embassy_executor::task(pool_size = 2)]
+pub async fn dhcp_fallback_task(stack: Stack<'static>, config: IfaceConfig) {
+    let Some(fixed) = config.fixed.filter(|_| config.dhcp) else {
+        return;
+    };
+    if stack
+        .wait_config_up()
+        .with_timeout(DHCP_TIMEOUT)
+        .await
+        .is_ok()
+    {
+        return;
+    }
+    crate::log_warn!(
+        "no DHCP lease after {}s, using {}",
+        DHCP_TIMEOUT.as_secs(),
+        fixed.address
+    );
+    stack.set_config_v4(ConfigV4::Static(fixed));
+}
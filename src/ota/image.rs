@@ -0,0 +1,160 @@
+use sha2::{Digest, Sha256};
+
+use super::OtaError;
+
+const MAGIC: u8 = 0xe9;
+const HEADER_LEN: usize = 24;
+const SEGMENT_HEADER_LEN: usize = 8;
+/// The ESP32 in the extended header's chip id.
+const CHIP_ID: u16 = 0;
+const MAX_SEGMENTS: u8 = 16;
+const CHECKSUM_SEED: u8 = 0xef;
+const HASH_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Header,
+    SegmentHeader,
+    Segment(u32),
+    /// Zero padding up to the checksum byte, which ends a 16 byte block.
+    Checksum,
+    /// The SHA-256 of everything before it, which the image carries when built with
+    /// `hash_appended`.
+    Hash(usize),
+    Done,
+}
+
+/// Checks an ESP application image while it streams by: magic, chip, segment layout, the
+/// XOR checksum after the last segment and the appended SHA-256, if any.
+pub struct ImageCheck {
+    stage: Stage,
+    header: [u8; HEADER_LEN],
+    filled: usize,
+    segments_left: u8,
+    hash_appended: bool,
+    checksum: u8,
+    sha: Sha256,
+    hash: [u8; HASH_LEN],
+    offset: usize,
+}
+
+impl ImageCheck {
+    pub fn new() -> Self {
+        Self {
+            stage: Stage::Header,
+            header: [0; HEADER_LEN],
+            filled: 0,
+            segments_left: 0,
+            hash_appended: false,
+            checksum: CHECKSUM_SEED,
+            sha: Sha256::new(),
+            hash: [0; HASH_LEN],
+            offset: 0,
+        }
+    }
+
+    /// Whether everything up to and including the checksum (and hash, if any) has been seen.
+    pub fn complete(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    /// Collects `len` header bytes; returns how much of `data` it used and whether it is full.
+    fn fill(&mut self, len: usize, data: &[u8]) -> (usize, bool) {
+        let n = (len - self.filled).min(data.len());
+        self.header[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+        self.filled += n;
+        (n, self.filled == len)
+    }
+
+    pub fn push(&mut self, mut data: &[u8]) -> Result<(), OtaError> {
+        while !data.is_empty() {
+            let hashed = !matches!(self.stage, Stage::Hash(_) | Stage::Done);
+            let used = match self.stage {
+                Stage::Header => {
+                    let (n, full) = self.fill(HEADER_LEN, data);
+                    if full {
+                        let h = &self.header;
+                        if h[0] != MAGIC {
+                            return Err(OtaError::BadImage("magic"));
+                        }
+                        if u16::from_le_bytes([h[12], h[13]]) != CHIP_ID {
+                            return Err(OtaError::BadImage("chip"));
+                        }
+                        if h[1] == 0 || h[1] > MAX_SEGMENTS {
+                            return Err(OtaError::BadImage("segment count"));
+                        }
+                        self.segments_left = h[1];
+                        self.hash_appended = h[23] == 1;
+                        self.filled = 0;
+                        self.stage = Stage::SegmentHeader;
+                    }
+                    n
+                }
+                Stage::SegmentHeader => {
+                    let (n, full) = self.fill(SEGMENT_HEADER_LEN, data);
+                    if full {
+                        let h = &self.header;
+                        let len = u32::from_le_bytes([h[4], h[5], h[6], h[7]]);
+                        if len as usize > super::MAX_IMAGE_LEN {
+                            return Err(OtaError::BadImage("segment length"));
+                        }
+                        self.filled = 0;
+                        self.stage = Stage::Segment(len);
+                    }
+                    n
+                }
+                Stage::Segment(left) => {
+                    let n = (left as usize).min(data.len());
+                    self.checksum = data[..n].iter().fold(self.checksum, |c, b| c ^ b);
+                    let left = left - n as u32;
+                    self.stage = if left > 0 {
+                        Stage::Segment(left)
+                    } else {
+                        self.segments_left -= 1;
+                        if self.segments_left == 0 {
+                            Stage::Checksum
+                        } else {
+                            Stage::SegmentHeader
+                        }
+                    };
+                    n
+                }
+                Stage::Checksum => {
+                    if self.offset % 16 == 15 {
+                        if data[0] != self.checksum {
+                            return Err(OtaError::Checksum);
+                        }
+                        self.stage = if self.hash_appended {
+                            Stage::Hash(HASH_LEN)
+                        } else {
+                            Stage::Done
+                        };
+                    }
+                    1
+                }
+                Stage::Hash(left) => {
+                    let n = left.min(data.len());
+                    let start = HASH_LEN - left;
+                    self.hash[start..start + n].copy_from_slice(&data[..n]);
+                    let left = left - n;
+                    if left > 0 {
+                        self.stage = Stage::Hash(left);
+                    } else if self.sha.clone().finalize()[..] != self.hash[..] {
+                        return Err(OtaError::BadImage("hash"));
+                    } else {
+                        self.stage = Stage::Done;
+                    }
+                    n
+                }
+                // tools pad images to the flash sector size
+                Stage::Done => data.len(),
+            };
+            if hashed {
+                self.sha.update(&data[..used]);
+            }
+            self.offset += used;
+            data = &data[used..];
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embedded_storage::Storage;
+use esp_bootloader_esp_idf::{
+    ota::OtaImageState,
+    ota_updater::OtaUpdater,
+    partitions::{self, PartitionType, PARTITION_TABLE_MAX_LEN},
+};
+use esp_storage::FlashStorage;
+
+use crate::MyHeapVec;
+
+pub use image::ImageCheck;
+pub use pull::{ota_cmd_from_mqtt, ota_task};
+
+mod image;
+mod pull;
+
+/// Size of the `ota_0`/`ota_1` partitions in `partitions.csv`.
+pub const MAX_IMAGE_LEN: usize = 0x10_0000;
+const SECTOR: usize = 4096;
+
+/// Held by the one `Update` allowed at a time, whether it came over HTTP or was pulled.
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, defmt::Format)]
+pub enum OtaError {
+    Busy,
+    Partition,
+    Flash,
+    TooBig,
+    BadImage(&'static str),
+    Checksum,
+    Incomplete,
+}
+
+/// Writes an application image into the OTA slot we are not running from, checking it as it
+/// goes, and makes it the boot slot once it is complete.
+pub struct Update {
+    flash: FlashStorage,
+    offset: u32,
+    capacity: usize,
+    written: usize,
+    sector: MyHeapVec<u8>,
+    filled: usize,
+    check: ImageCheck,
+}
+
+impl Update {
+    pub fn begin() -> Result<Self, OtaError> {
+        if BUSY.swap(true, Ordering::Acquire) {
+            return Err(OtaError::Busy);
+        }
+        Self::open().inspect_err(|_| BUSY.store(false, Ordering::Release))
+    }
+
+    fn open() -> Result<Self, OtaError> {
+        let mut flash = FlashStorage::new();
+        let mut buffer = [0u8; PARTITION_TABLE_MAX_LEN];
+        let kind = {
+            let mut ota =
+                OtaUpdater::new(&mut flash, &mut buffer).map_err(|_| OtaError::Partition)?;
+            let (_, kind) = ota.next_partition().map_err(|_| OtaError::Partition)?;
+            kind
+        };
+        let table = partitions::read_partition_table(&mut flash, &mut buffer)
+            .map_err(|_| OtaError::Partition)?;
+        let slot = table
+            .find_partition(PartitionType::App(kind))
+            .map_err(|_| OtaError::Partition)?
+            .ok_or(OtaError::Partition)?;
+        crate::log_info!("ota: writing {:?} at {:x}", kind, slot.offset());
+        Ok(Self {
+            offset: slot.offset(),
+            capacity: slot.len() as usize,
+            flash,
+            written: 0,
+            sector: crate::vec_in_myheap!(0u8; SECTOR),
+            filled: 0,
+            check: ImageCheck::new(),
+        })
+    }
+
+    /// Bytes accepted so far.
+    pub fn written(&self) -> usize {
+        self.written + self.filled
+    }
+
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), OtaError> {
+        if self.written() + data.len() > self.capacity {
+            return Err(OtaError::TooBig);
+        }
+        self.check.push(data)?;
+        while !data.is_empty() {
+            let n = (SECTOR - self.filled).min(data.len());
+            self.sector[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == SECTOR {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), OtaError> {
+        self.flash
+            .write(
+                self.offset + self.written as u32,
+                &self.sector[..self.filled],
+            )
+            .map_err(|_| OtaError::Flash)?;
+        self.written += self.filled;
+        self.filled = 0;
+        Ok(())
+    }
+
+    /// Writes what is left and switches `otadata` to the new slot. Takes effect on restart.
+    pub fn finish(mut self) -> Result<(), OtaError> {
+        if !self.check.complete() {
+            return Err(OtaError::Incomplete);
+        }
+        self.flush()?;
+        let mut buffer = [0u8; PARTITION_TABLE_MAX_LEN];
+        let mut ota =
+            OtaUpdater::new(&mut self.flash, &mut buffer).map_err(|_| OtaError::Partition)?;
+        ota.activate_next_partition()
+            .map_err(|_| OtaError::Partition)?;
+        ota.set_current_ota_state(OtaImageState::New)
+            .map_err(|_| OtaError::Partition)?;
+        crate::log_info!("ota: {} bytes installed", self.written);
+        Ok(())
+    }
+}
+
+impl Drop for Update {
+    fn drop(&mut self) {
+        BUSY.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,400 @@
+use alloc::format;
+use core::fmt::Write;
+
+use embassy_futures::select::select;
+use embassy_net::{
+    dns::DnsSocket,
+    tcp::client::{TcpClient, TcpClientState},
+    Stack,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{with_timeout, Duration, Timer};
+use esp_hal::rng::Rng;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use reqwless::{
+    client::{HttpClient, TlsConfig, TlsVerify},
+    request::{Method, RequestBuilder},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    bus::{self, Channel},
+    link,
+    supervisor::{self, Heartbeat},
+    MyHeapVec,
+};
+
+use super::{OtaError, Update, MAX_IMAGE_LEN};
+
+/// Where the manifest is published. Without it the device only updates when pushed to.
+const OTA_URL: Option<&str> = option_env!("OTA_URL");
+/// The P-256 key manifests are signed with, SEC1-encoded in hex. build.rs requires it with
+/// `OTA_URL`.
+const OTA_PUBKEY: Option<&str> = option_env!("OTA_PUBKEY");
+/// Appended to `OTA_URL` for the manifest's signature.
+const SIGNATURE_SUFFIX: &str = ".sig";
+const DEFAULT_INTERVAL_SECS: u64 = 6 * 60 * 60;
+/// Leave the network some time to settle before the first check.
+const FIRST_CHECK: Duration = Duration::from_secs(60);
+/// Bytes asked for per request. Each range is read whole before it is written out.
+const RANGE_LEN: usize = 16 * 1024;
+/// Room for the response headers in front of a range.
+const HEADER_ROOM: usize = 1024;
+/// Enough for a full TLS record.
+const TLS_READ_LEN: usize = 16640;
+const TLS_WRITE_LEN: usize = 4096;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Failed ranges in a row before the download is given up; each one resumes where it stopped.
+const MAX_RETRIES: u32 = 5;
+/// The task checks in before each request and range, each bounded by `REQUEST_TIMEOUT`.
+const STUCK_AFTER: Duration = Duration::from_secs(3 * REQUEST_TIMEOUT.as_secs());
+
+/// Raised by `/rpc/ota` to check right away.
+static CHECK: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// `{"version":"<git hash>","seq":123,"url":"https://.../app.bin","size":123456,"sha256":"<hex>"}`.
+/// A `url` starting with `/` is taken relative to the manifest's host. `seq` is the image's
+/// `BUILD_SEQ`; only an image with a higher one than ours is installed.
+///
+/// The certificate of the server is not checked, so the manifest is only trusted when
+/// `OTA_URL.sig` holds a valid ECDSA signature of it by `OTA_PUBKEY`, as 128 hex digits of
+/// `r` then `s`. The image is then trusted through `sha256`.
+#[derive(Deserialize)]
+struct Manifest {
+    version: heapless::String<40>,
+    seq: u32,
+    url: heapless::String<256>,
+    size: usize,
+    sha256: heapless::String<64>,
+}
+
+#[derive(Debug, defmt::Format)]
+enum PullError {
+    NoUplink,
+    Url,
+    Http,
+    Timeout,
+    Status,
+    Range,
+    Manifest,
+    Signature,
+    Hash,
+    Ota(OtaError),
+}
+
+impl From<OtaError> for PullError {
+    fn from(e: OtaError) -> Self {
+        PullError::Ota(e)
+    }
+}
+
+impl From<reqwless::Error> for PullError {
+    fn from(e: reqwless::Error) -> Self {
+        crate::log_warn!("ota: http {:?}", e);
+        PullError::Http
+    }
+}
+
+impl From<embassy_time::TimeoutError> for PullError {
+    fn from(_: embassy_time::TimeoutError) -> Self {
+        PullError::Timeout
+    }
+}
+
+struct Buffers {
+    rx: MyHeapVec<u8>,
+    tls_read: MyHeapVec<u8>,
+    tls_write: MyHeapVec<u8>,
+}
+
+fn interval() -> Duration {
+    let secs = option_env!("OTA_INTERVAL")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+    Duration::from_secs(secs)
+}
+
+fn log(msg: &str) {
+    bus::try_send(Channel::OtaLog, msg.as_bytes());
+}
+
+/// Splits `https://host[:port]/path` into the part reqwless connects to and the path.
+fn split_url(url: &str) -> Result<(&str, &str), PullError> {
+    let host = url.find("://").ok_or(PullError::Url)? + 3;
+    match url[host..].find('/') {
+        Some(i) => Ok(url.split_at(host + i)),
+        None => Ok((url, "/")),
+    }
+}
+
+fn parse_hex<const N: usize>(hex: &str) -> Option<heapless::Vec<u8, N>> {
+    // collecting past N would panic
+    if hex.len() % 2 != 0 || hex.len() > 2 * N {
+        return None;
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn parse_sha256(hex: &str) -> Result<[u8; 32], PullError> {
+    parse_hex::<32>(hex)
+        .and_then(|hash| hash.into_array().ok())
+        .ok_or(PullError::Manifest)
+}
+
+/// Checks `signature`, the body of `OTA_URL.sig`, against the raw manifest.
+fn verify(manifest: &[u8], signature: &[u8]) -> Result<(), PullError> {
+    let key = OTA_PUBKEY
+        .and_then(parse_hex::<65>)
+        .and_then(|key| VerifyingKey::from_sec1_bytes(&key).ok())
+        .ok_or(PullError::Signature)?;
+    let signature = core::str::from_utf8(signature)
+        .ok()
+        .and_then(|hex| parse_hex::<64>(hex.trim()))
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or(PullError::Signature)?;
+    key.verify(manifest, &signature)
+        .map_err(|_| PullError::Signature)
+}
+
+fn seed() -> u64 {
+    let rng = Rng::new();
+    (rng.random() as u64) << 32 | rng.random() as u64
+}
+
+fn stack() -> Result<Stack<'static>, PullError> {
+    link::active().map(|u| u.stack).ok_or(PullError::NoUplink)
+}
+
+async fn fetch_manifest(url: &str, bufs: &mut Buffers) -> Result<Manifest, PullError> {
+    let stack = stack()?;
+    let state = TcpClientState::<1, 4096, 4096>::new();
+    let tcp = TcpClient::new(stack, &state);
+    let dns = DnsSocket::new(stack);
+    let tls = TlsConfig::new(
+        seed(),
+        &mut bufs.tls_read[..],
+        &mut bufs.tls_write[..],
+        TlsVerify::None,
+    );
+    let mut client = HttpClient::new_with_tls(&tcp, &dns, tls);
+    let mut sig_url = heapless::String::<320>::new();
+    write!(sig_url, "{}{}", url, SIGNATURE_SUFFIX).map_err(|_| PullError::Url)?;
+
+    let fetch = async {
+        // the signature first: it is small enough to keep while the manifest takes the buffer
+        let signature = {
+            let mut request = client.request(Method::GET, &sig_url).await?;
+            let response = request.send(&mut bufs.rx[..]).await?;
+            if !response.status.is_successful() {
+                crate::log_warn!("ota: http status {:?}", response.status);
+                return Err(PullError::Status);
+            }
+            let body = response.body().read_to_end().await?;
+            heapless::Vec::<u8, 256>::from_slice(body).map_err(|_| PullError::Signature)?
+        };
+
+        let mut request = client.request(Method::GET, url).await?;
+        let response = request.send(&mut bufs.rx[..]).await?;
+        if !response.status.is_successful() {
+            crate::log_warn!("ota: http status {:?}", response.status);
+            return Err(PullError::Status);
+        }
+        let body = response.body().read_to_end().await?;
+        verify(body, &signature)?;
+        serde_json_core::from_slice::<Manifest>(body)
+            .map(|(manifest, _)| manifest)
+            .map_err(|_| PullError::Manifest)
+    };
+    with_timeout(REQUEST_TIMEOUT, fetch).await?
+}
+
+/// Fetches the image range by range from where `update` stopped, until it has all of it.
+async fn download_ranges(
+    url: &str,
+    size: usize,
+    update: &mut Update,
+    sha: &mut Sha256,
+    bufs: &mut Buffers,
+    heartbeat: &Heartbeat,
+) -> Result<(), PullError> {
+    heartbeat.beat();
+    let (base, path) = split_url(url)?;
+    let stack = stack()?;
+    let state = TcpClientState::<1, 4096, 4096>::new();
+    let tcp = TcpClient::new(stack, &state);
+    let dns = DnsSocket::new(stack);
+    let tls = TlsConfig::new(
+        seed(),
+        &mut bufs.tls_read[..],
+        &mut bufs.tls_write[..],
+        TlsVerify::None,
+    );
+    let mut client = HttpClient::new_with_tls(&tcp, &dns, tls);
+    // one connection for all ranges, as long as the server keeps it open
+    let mut resource = with_timeout(REQUEST_TIMEOUT, client.resource(base)).await??;
+
+    while update.written() < size {
+        heartbeat.beat();
+        let start = update.written();
+        let end = (start + RANGE_LEN).min(size) - 1;
+        let mut range = heapless::String::<40>::new();
+        write!(range, "bytes={}-{}", start, end).ok();
+
+        let fetch = async {
+            let response = resource
+                .get(path)
+                .headers(&[("Range", range.as_str())])
+                .send(&mut bufs.rx[..])
+                .await?;
+            if !response.status.is_successful() {
+                crate::log_warn!("ota: http status {:?}", response.status);
+                return Err(PullError::Status);
+            }
+            Ok(response.body().read_to_end().await?)
+        };
+        let body = with_timeout(REQUEST_TIMEOUT, fetch).await??;
+        // a server ignoring Range sends the whole file, which does not fit
+        if body.len() != end + 1 - start {
+            return Err(PullError::Range);
+        }
+        sha.update(&*body);
+        update.write(body)?;
+    }
+    Ok(())
+}
+
+/// Downloads and verifies the image the manifest points to and makes it the boot slot.
+async fn install(
+    manifest: &Manifest,
+    bufs: &mut Buffers,
+    heartbeat: &Heartbeat,
+) -> Result<(), PullError> {
+    if manifest.size > MAX_IMAGE_LEN {
+        return Err(OtaError::TooBig.into());
+    }
+    let expected = parse_sha256(&manifest.sha256)?;
+    let mut url = heapless::String::<512>::new();
+    if manifest.url.starts_with('/') {
+        let (base, _) = split_url(OTA_URL.unwrap_or_default())?;
+        url.push_str(base).ok();
+    }
+    url.push_str(&manifest.url).map_err(|_| PullError::Url)?;
+
+    let mut update = Update::begin()?;
+    let mut sha = Sha256::new();
+    let mut failures = 0;
+    loop {
+        let downloaded =
+            download_ranges(&url, manifest.size, &mut update, &mut sha, bufs, heartbeat).await;
+        match downloaded {
+            Ok(()) => break,
+            // the image itself is bad, fetching it again will not help
+            Err(PullError::Ota(e)) => return Err(e.into()),
+            Err(e) if failures < MAX_RETRIES => {
+                failures += 1;
+                crate::log_warn!(
+                    "ota: download stopped at {} ({:?}), resuming",
+                    update.written(),
+                    e
+                );
+                Timer::after_secs(5 * failures as u64).await;
+            }
+            Err(e) => return Err(e),
+        }
+        log(&format!(
+            "downloaded {} of {}",
+            update.written(),
+            manifest.size
+        ));
+    }
+
+    if sha.finalize()[..] != expected[..] {
+        return Err(PullError::Hash);
+    }
+    update.finish()?;
+    Ok(())
+}
+
+/// Where the running image is in the sequence of builds, see `BUILD_SEQ` in build.rs.
+fn build_seq() -> u32 {
+    env!("BUILD_SEQ").parse().unwrap_or(0)
+}
+
+/// Returns whether a new image was installed.
+async fn check(url: &str, heartbeat: &Heartbeat) -> Result<bool, PullError> {
+    let mut bufs = Buffers {
+        rx: crate::vec_in_myheap!(0u8; RANGE_LEN + HEADER_ROOM),
+        tls_read: crate::vec_in_myheap!(0u8; TLS_READ_LEN),
+        tls_write: crate::vec_in_myheap!(0u8; TLS_WRITE_LEN),
+    };
+    let manifest = fetch_manifest(url, &mut bufs).await?;
+    let seq = build_seq();
+    if manifest.seq == seq {
+        crate::log_info!("ota: {} is current", env!("GIT_HASH"));
+        return Ok(false);
+    }
+    // a signed manifest stays valid, so an old one could be served again
+    if manifest.seq < seq {
+        crate::log_warn!(
+            "ota: manifest for {} (seq {}) is older than ours ({}), ignored",
+            manifest.version.as_str(),
+            manifest.seq,
+            seq
+        );
+        return Ok(false);
+    }
+    crate::log_info!(
+        "ota: updating {} -> {}",
+        env!("GIT_HASH"),
+        manifest.version.as_str()
+    );
+    log(&format!(
+        "updating {} -> {}",
+        env!("GIT_HASH"),
+        manifest.version
+    ));
+    install(&manifest, &mut bufs, heartbeat).await?;
+    Ok(true)
+}
+
+/// `check` looks for an update now instead of waiting for the next periodic check.
+pub fn ota_cmd_from_mqtt(payload: &[u8]) {
+    match payload {
+        b"check" => CHECK.signal(()),
+        _ => crate::log_warn!("unknown ota command {}", payload.escape_ascii()),
+    }
+}
+
+/// Checks the manifest at `OTA_URL` every `OTA_INTERVAL` seconds (and on `/rpc/ota`) and
+/// installs the image it names when it is newer than ours.
+#[embassy_executor::task]
+pub async fn ota_task() {
+    let Some(url) = OTA_URL else {
+        crate::log_info!("ota: no OTA_URL, pull updates disabled");
+        return;
+    };
+    let heartbeat = supervisor::register("ota", None, STUCK_AFTER);
+    let mut wait = FIRST_CHECK;
+    loop {
+        heartbeat.idle();
+        select(Timer::after(wait), CHECK.wait()).await;
+        heartbeat.beat();
+        wait = interval();
+        match check(url, &heartbeat).await {
+            Ok(false) => {}
+            Ok(true) => {
+                log("installed, restarting");
+                Timer::after_secs(2).await;
+                esp_hal::system::software_reset();
+            }
+            Err(e) => {
+                crate::log_error!("ota: {:?}", e);
+                log(&format!("failed: {:?}", e));
+            }
+        }
+    }
+}
@@ -1,29 +1,28 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
 use embassy_time::{Instant, WithTimeout};
 use esp_hal::gpio::Output;
 use mountain_mqtt::{client::EventHandlerError, packets::publish::ApplicationMessage};
 
 pub const NUM_OUT: usize = 4;
-type Packet = [Option<u8>; NUM_OUT];
+pub type Packet = [Option<u8>; NUM_OUT];
 
 static WRITE: Channel<CriticalSectionRawMutex, Packet, 2> = Channel::new();
+static ON: [AtomicBool; NUM_OUT] = [const { AtomicBool::new(false) }; NUM_OUT];
 
-
-#[allow(dead_code)]
-pub async fn output_state(relays: Packet) {
-    WRITE.send(relays).await;
+/// Whether each relay is currently energised.
+pub fn relays() -> [bool; NUM_OUT] {
+    core::array::from_fn(|i| ON[i].load(Ordering::Relaxed))
 }
 
-pub async fn output_state_from_mqtt<const P: usize>(
-    message: ApplicationMessage<'_, P>,
-) -> Result<(), EventHandlerError> {
-    let ascii = core::str::from_utf8(message.payload)
-        .map_err(|_| EventHandlerError::InvalidApplicationMessage)?;
-    defmt::info!("{}", ascii);
+/// Parses hex pairs, one per relay, into seconds to stay on: `00` off, `ff` on for good.
+/// Missing or invalid pairs leave their relay alone.
+pub fn parse_hex(ascii: &str) -> Packet {
     let mut bytes = [None; NUM_OUT];
     for (i, chunk) in ascii.as_bytes().chunks(2).take(NUM_OUT).enumerate() {
         if chunk.len() != 2 {
-            defmt::error!("Incomplete hex pair at index {}", i);
+            crate::log_error!("Incomplete hex pair at index {}", i);
             continue;
         }
         match core::str::from_utf8(chunk)
@@ -31,13 +30,36 @@ pub async fn output_state_from_mqtt<const P: usize>(
             .and_then(|hex| u8::from_str_radix(hex, 16).ok())
         {
             Some(byte) => bytes[i] = Some(byte),
-            None => defmt::error!("Invalid hex pair at index {}", i),
+            None => crate::log_error!("Invalid hex pair at index {}", i),
         }
     }
-    WRITE.send(bytes).await;
+    bytes
+}
+
+pub async fn output_state(relays: Packet) {
+    WRITE.send(relays).await;
+}
+
+pub async fn output_state_from_mqtt<const P: usize>(
+    message: ApplicationMessage<'_, P>,
+) -> Result<(), EventHandlerError> {
+    let ascii = core::str::from_utf8(message.payload)
+        .map_err(|_| EventHandlerError::InvalidApplicationMessage)?;
+    crate::log_info!("{}", ascii);
+    WRITE.send(parse_hex(ascii)).await;
     Ok(())
 }
 
+/// Relays are active low.
+fn switch(pin: &mut Output<'static>, i: usize, on: bool) {
+    if on {
+        pin.set_low();
+    } else {
+        pin.set_high();
+    }
+    ON[i].store(on, Ordering::Relaxed);
+}
+
 #[embassy_executor::task]
 pub async fn output_task(mut pins: [Output<'static>; NUM_OUT]) {
     let mut timers: [Option<Instant>; NUM_OUT] = [None; NUM_OUT];
@@ -55,15 +77,15 @@ pub async fn output_task(mut pins: [Output<'static>; NUM_OUT]) {
                     if let Some(seconds) = value {
                         if seconds == 0u8 {
                             timers[i] = None;
-                            pins[i].set_high();
+                            switch(&mut pins[i], i, false);
                         } else if seconds == 255u8 {
                             timers[i] = Some(Instant::MAX);
-                            pins[i].set_low();
+                            switch(&mut pins[i], i, true);
                         } else {
                             timers[i] = Some(
                                 Instant::now() + embassy_time::Duration::from_secs(seconds as u64),
                             );
-                            pins[i].set_low();
+                            switch(&mut pins[i], i, true);
                         }
                     }
                 }
@@ -72,10 +94,10 @@ pub async fn output_task(mut pins: [Output<'static>; NUM_OUT]) {
                 for (i, pin) in pins.iter_mut().enumerate() {
                     if let Some(timer) = timers[i] {
                         if timer < Instant::now() {
-                            pin.set_high();
+                            switch(pin, i, false);
                             timers[i] = None;
                         } else {
-                            pin.set_low();
+                            switch(pin, i, true);
                         }
                     }
                 }
@@ -0,0 +1,109 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embedded_storage::{ReadStorage, Storage};
+use esp_bootloader_esp_idf::partitions::{
+    self, DataPartitionSubType, PartitionType, PARTITION_TABLE_MAX_LEN,
+};
+use esp_storage::FlashStorage;
+use serde::{Deserialize, Serialize};
+
+/// Marks a settings record written by this firmware, `"SET1"`.
+const MAGIC: u32 = 0x5345_5431;
+/// Magic followed by the length of the postcard payload.
+const HEADER_LEN: usize = 6;
+const MAX_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WifiCredentials {
+    pub ssid: heapless::String<32>,
+    pub password: heapless::String<64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Broker {
+    pub host: heapless::String<64>,
+    pub port: u16,
+}
+
+/// Settings changed at runtime and kept across reboots, in the `nvs` partition.
+///
+/// Anything left at `None` falls back to the value baked in at build time.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    pub wifi: Option<WifiCredentials>,
+    pub broker: Option<Broker>,
+}
+
+#[derive(Debug, defmt::Format)]
+pub enum SettingsError {
+    PartitionTable,
+    NoPartition,
+    Flash,
+    Encode,
+}
+
+static SETTINGS: Mutex<CriticalSectionRawMutex, RefCell<Option<Settings>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Flash offset of the `nvs` partition, from the partition table.
+pub fn partition_offset(flash: &mut FlashStorage) -> Result<u32, SettingsError> {
+    let mut table = [0u8; PARTITION_TABLE_MAX_LEN];
+    let table = partitions::read_partition_table(flash, &mut table)
+        .map_err(|_| SettingsError::PartitionTable)?;
+    let nvs = table
+        .find_partition(PartitionType::Data(DataPartitionSubType::Nvs))
+        .map_err(|_| SettingsError::PartitionTable)?
+        .ok_or(SettingsError::NoPartition)?;
+    Ok(nvs.offset())
+}
+
+fn load() -> Result<Settings, SettingsError> {
+    let mut flash = FlashStorage::new();
+    let offset = partition_offset(&mut flash)?;
+    let mut buf = crate::vec_in_myheap!(0u8; MAX_LEN);
+    flash
+        .read(offset, &mut buf[..])
+        .map_err(|_| SettingsError::Flash)?;
+    let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let len = u16::from_le_bytes([buf[4], buf[5]]) as usize;
+    if magic != MAGIC || HEADER_LEN + len > MAX_LEN {
+        return Ok(Settings::default());
+    }
+    Ok(postcard::from_bytes(&buf[HEADER_LEN..HEADER_LEN + len]).unwrap_or_default())
+}
+
+/// Reads the settings from flash. Call once at boot, before anything asks for them.
+pub fn init() {
+    let settings = load()
+        .inspect_err(|e| crate::log_error!("settings load {:?}", e))
+        .unwrap_or_default();
+    // serial only, the settings hold the wifi password
+    defmt::info!("settings: {}", defmt::Debug2Format(&settings));
+    SETTINGS.lock(|s| s.replace(Some(settings)));
+}
+
+pub fn get() -> Settings {
+    SETTINGS.lock(|s| s.borrow().clone().unwrap_or_default())
+}
+
+/// Applies `f` to the settings and persists the result.
+pub fn update(f: impl FnOnce(&mut Settings)) -> Result<(), SettingsError> {
+    let mut settings = get();
+    f(&mut settings);
+
+    let mut buf = crate::vec_in_myheap!(0u8; MAX_LEN);
+    let len = postcard::to_slice(&settings, &mut buf[HEADER_LEN..])
+        .map_err(|_| SettingsError::Encode)?
+        .len();
+    buf[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    buf[4..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+    let mut flash = FlashStorage::new();
+    let offset = partition_offset(&mut flash)?;
+    flash
+        .write(offset, &buf[..HEADER_LEN + len])
+        .map_err(|_| SettingsError::Flash)?;
+
+    SETTINGS.lock(|s| s.replace(Some(settings)));
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use core::{cell::RefCell, fmt::Write};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+use crate::{http::HTTP_TASKS, mdns::MDNS_TASKS, tcp::TCP_MAX_CLIENTS, uart::UART_PORTS};
+
+/// Tasks spawned once that register: mqtt or webhook, ble, udp, ota, link, logger, memory and
+/// crash.
+const SINGLE_TASKS: usize = 8;
+/// Room for every task that registers, including those spawned per port, slot or interface.
+const MAX_TASKS: usize = SINGLE_TASKS + UART_PORTS + TCP_MAX_CLIENTS + HTTP_TASKS + MDNS_TASKS;
+
+struct Entry {
+    name: &'static str,
+    /// For tasks spawned once per port or slot.
+    slot: Option<usize>,
+    timeout: Duration,
+    /// `None` while the task waits on something that may legitimately never come, like a
+    /// client or an uplink.
+    deadline: Option<Instant>,
+}
+
+static TASKS: Mutex<CriticalSectionRawMutex, RefCell<heapless::Vec<Entry, MAX_TASKS>>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// A task's promise to check in at least every `timeout` while it is busy. `None` for a task
+/// that found no room in the table, which then goes unsupervised.
+pub struct Heartbeat(Option<usize>);
+
+/// Puts the calling task under supervision, starting now. A task that then goes `timeout`
+/// without a `beat` is reported by `stuck`, and `main` stops feeding the watchdog.
+pub fn register(name: &'static str, slot: Option<usize>, timeout: Duration) -> Heartbeat {
+    let index = TASKS.lock(|t| {
+        let mut tasks = t.borrow_mut();
+        let entry = Entry {
+            name,
+            slot,
+            timeout,
+            deadline: Some(Instant::now() + timeout),
+        };
+        tasks.push(entry).ok().map(|_| tasks.len() - 1)
+    });
+    if index.is_none() {
+        crate::log_error!("no room to supervise {} {:?}, raise MAX_TASKS", name, slot);
+    }
+    Heartbeat(index)
+}
+
+impl Heartbeat {
+    fn set(&self, deadline: impl FnOnce(&Entry) -> Option<Instant>) {
+        let Some(index) = self.0 else {
+            return;
+        };
+        TASKS.lock(|t| {
+            let entry = &mut t.borrow_mut()[index];
+            entry.deadline = deadline(entry);
+        })
+    }
+
+    /// The task is making progress.
+    pub fn beat(&self) {
+        self.set(|entry| Some(Instant::now() + entry.timeout))
+    }
+
+    /// The task is about to wait for something outside its control; it is not supervised
+    /// again until the next `beat`.
+    pub fn idle(&self) {
+        self.set(|_| None)
+    }
+}
+
+/// A task that gives up or finishes is not waited for.
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.idle()
+    }
+}
+
+/// The first registered task past its deadline, as `name` or `name[slot]`.
+pub fn stuck() -> Option<heapless::String<24>> {
+    let now = Instant::now();
+    TASKS.lock(|t| {
+        let tasks = t.borrow();
+        let entry = tasks
+            .iter()
+            .find(|e| e.deadline.is_some_and(|deadline| deadline < now))?;
+        let mut name = heapless::String::new();
+        match entry.slot {
+            Some(slot) => write!(name, "{}[{}]", entry.name, slot).ok(),
+            None => write!(name, "{}", entry.name).ok(),
+        };
+        Some(name)
+    })
+}
@@ -0,0 +1,93 @@
+use core::sync::atomic::Ordering;
+
+use embassy_futures::select::select;
+use embassy_net::{tcp::TcpSocket, IpEndpoint, Stack};
+use embassy_time::{Duration, Timer};
+
+use crate::{led, link, supervisor::Heartbeat};
+
+use super::server::{register, serve, TcpConfig, CONNECTED};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Dials `TcpConfig::remote` and bridges the connection to `slot` exactly like an accepted
+/// client, reconnecting with exponential backoff when it fails or drops, and over the new
+/// interface when the uplink switches.
+#[embassy_executor::task]
+pub async fn tcp_client_task(slot: usize, config: TcpConfig) {
+    let remote = config
+        .remote
+        .and_then(|remote| remote.rsplit_once(':'))
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)));
+    let Some((host, port)) = remote else {
+        crate::log_error!("tcp client: invalid remote {:?}", config.remote);
+        return;
+    };
+
+    let mut rx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut tx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut uplink = link::receiver();
+    let heartbeat = register(slot, &config);
+
+    loop {
+        heartbeat.idle();
+        let stack = uplink.get().await.stack;
+        select(
+            dial_loop(
+                stack,
+                (host, port),
+                slot,
+                &config,
+                &mut rx_buffer[..],
+                &mut tx_buffer[..],
+                &heartbeat,
+            ),
+            uplink.changed(),
+        )
+        .await;
+        CONNECTED[slot].store(false, Ordering::Relaxed);
+    }
+}
+
+async fn dial_loop(
+    stack: Stack<'static>,
+    (host, port): (&str, u16),
+    slot: usize,
+    config: &TcpConfig,
+    rx_buffer: &mut [u8],
+    tx_buffer: &mut [u8],
+    heartbeat: &Heartbeat,
+) -> ! {
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        heartbeat.beat();
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer[..], &mut tx_buffer[..]);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+
+        let addr = match stack.dns_query(host, smoltcp::wire::DnsQueryType::A).await {
+            Ok(addrs) if !addrs.is_empty() => addrs[0],
+            result => {
+                crate::log_error!("tcp client dns {:?}", result);
+                Timer::after(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+
+        if let Err(e) = socket.connect(IpEndpoint::new(addr, port)).await {
+            crate::log_error!("tcp client connect {:?}", e);
+            led::state(led::LedState::TCP(false)).await;
+            Timer::after(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            continue;
+        }
+
+        crate::log_info!("tcp[{}] connected to {}:{}", slot, host, port);
+        led::state(led::LedState::TCP(true)).await;
+        backoff = MIN_BACKOFF;
+        serve(&mut socket, slot, config, heartbeat).await;
+        Timer::after(backoff).await;
+    }
+}
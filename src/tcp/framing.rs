@@ -0,0 +1,207 @@
+use crate::MyHeapVec;
+
+/// How the byte stream from a client is cut into messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    /// Frames end with `\n`, which is kept in the frame.
+    Newline,
+    /// Frames end with `\r\n`, which is kept in the frame.
+    CrLf,
+    /// Frames end with the given byte, which is kept in the frame.
+    Delimiter(u8),
+    /// Each frame is preceded by its length as a big-endian `u16`; the prefix is not kept.
+    LengthPrefix,
+}
+
+/// What to do with a frame that does not fit in the buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Oversize {
+    /// Emit the first part and discard the rest of the frame.
+    Truncate,
+    /// Emit the frame in buffer-sized pieces.
+    Split,
+    /// Discard the whole frame.
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// A complete frame is ready in `Framer::frame`.
+    Frame,
+    /// The frame was too big; its first part is ready in `Framer::frame`.
+    Truncated,
+    /// The frame was too big; a piece of it is ready in `Framer::frame`.
+    Split,
+    /// The frame was too big and has been discarded.
+    Dropped,
+}
+
+/// Reassembles frames from a byte stream. Holds no socket, so it can be fed from anywhere.
+pub struct Framer {
+    framing: Framing,
+    oversize: Oversize,
+    buf: MyHeapVec<u8>,
+    len: usize,
+    /// Skipping the tail of an oversize frame.
+    discarding: bool,
+    last: u8,
+    header: [u8; 2],
+    header_len: usize,
+    /// Bytes still expected for the current length-prefixed frame.
+    remaining: usize,
+}
+
+impl Framer {
+    pub fn new(framing: Framing, oversize: Oversize, capacity: usize) -> Self {
+        Self {
+            framing,
+            oversize,
+            buf: crate::vec_in_myheap!(0u8; capacity),
+            len: 0,
+            discarding: false,
+            last: 0,
+            header: [0; 2],
+            header_len: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Bytes of the frame ready to be handed out after an event other than `Dropped`.
+    pub fn frame(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Forget the frame returned by `frame`.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// A frame has been started but not finished, and `flush` would hand it out. A
+    /// length-prefixed frame never is: its header says where it ends, and a pause does not.
+    pub fn flushable(&self) -> bool {
+        self.framing != Framing::LengthPrefix && self.len > 0
+    }
+
+    /// Hands out whatever was received so far, for the idle flush. Leaves a length-prefixed
+    /// frame alone, so the stream stays in step with its headers.
+    pub fn flush(&mut self) -> Option<Event> {
+        if self.framing == Framing::LengthPrefix {
+            return None;
+        }
+        self.discarding = false;
+        self.flushable().then_some(Event::Frame)
+    }
+
+    /// Consumes bytes from `data` until the end of `data` or until an event, and returns the
+    /// number of bytes consumed.
+    pub fn push(&mut self, data: &[u8]) -> (usize, Option<Event>) {
+        match self.framing {
+            Framing::LengthPrefix => self.push_prefixed(data),
+            _ => self.push_delimited(data),
+        }
+    }
+
+    fn is_end(&self, byte: u8) -> bool {
+        match self.framing {
+            Framing::Newline => byte == b'\n',
+            Framing::CrLf => byte == b'\n' && self.last == b'\r',
+            Framing::Delimiter(d) => byte == d,
+            Framing::LengthPrefix => false,
+        }
+    }
+
+    fn push_delimited(&mut self, data: &[u8]) -> (usize, Option<Event>) {
+        for (i, &byte) in data.iter().enumerate() {
+            let end = self.is_end(byte);
+            if self.discarding {
+                self.last = byte;
+                self.discarding = !end;
+                continue;
+            }
+            if self.len == self.buf.len() {
+                match self.oversize {
+                    // leave the byte for the next piece
+                    Oversize::Split => return (i, Some(Event::Split)),
+                    Oversize::Truncate => {
+                        self.discarding = !end;
+                        self.last = byte;
+                        return (i + 1, Some(Event::Truncated));
+                    }
+                    Oversize::Drop => {
+                        self.discarding = !end;
+                        self.last = byte;
+                        self.len = 0;
+                        return (i + 1, Some(Event::Dropped));
+                    }
+                }
+            }
+            self.buf[self.len] = byte;
+            self.len += 1;
+            self.last = byte;
+            if end {
+                return (i + 1, Some(Event::Frame));
+            }
+        }
+        (data.len(), None)
+    }
+
+    fn push_prefixed(&mut self, data: &[u8]) -> (usize, Option<Event>) {
+        let mut i = 0;
+        while i < data.len() {
+            if self.header_len < 2 {
+                self.header[self.header_len] = data[i];
+                self.header_len += 1;
+                i += 1;
+                if self.header_len == 2 {
+                    self.remaining = u16::from_be_bytes(self.header) as usize;
+                    if self.remaining == 0 {
+                        self.header_len = 0;
+                        continue;
+                    }
+                    if self.remaining > self.buf.len() && self.oversize != Oversize::Split {
+                        self.discarding = true;
+                    }
+                }
+                continue;
+            }
+
+            let take = self.remaining.min(data.len() - i);
+            if self.discarding {
+                let keep = match self.oversize {
+                    Oversize::Truncate => take.min(self.buf.len() - self.len),
+                    _ => 0,
+                };
+                self.buf[self.len..self.len + keep].copy_from_slice(&data[i..i + keep]);
+                self.len += keep;
+                self.remaining -= take;
+                i += take;
+                if self.remaining == 0 {
+                    self.header_len = 0;
+                    self.discarding = false;
+                    return match self.oversize {
+                        Oversize::Truncate => (i, Some(Event::Truncated)),
+                        _ => {
+                            self.len = 0;
+                            (i, Some(Event::Dropped))
+                        }
+                    };
+                }
+                continue;
+            }
+
+            let take = take.min(self.buf.len() - self.len);
+            self.buf[self.len..self.len + take].copy_from_slice(&data[i..i + take]);
+            self.len += take;
+            self.remaining -= take;
+            i += take;
+            if self.remaining == 0 {
+                self.header_len = 0;
+                return (i, Some(Event::Frame));
+            }
+            if self.len == self.buf.len() {
+                return (i, Some(Event::Split));
+            }
+        }
+        (data.len(), None)
+    }
+}
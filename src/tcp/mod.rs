@@ -0,0 +1,6 @@
+pub use client::tcp_client_task;
+pub use server::{tcp_send, tcp_task, TcpConfig, TCP_MAX_CLIENTS};
+
+mod client;
+mod framing;
+mod server;
@@ -0,0 +1,309 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::{self, select, select3};
+use embassy_net::{tcp::TcpSocket, Stack};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Timer, WithTimeout};
+use embedded_io_async::Write;
+
+use crate::{
+    bus, led, link,
+    mqtt::MQTT_PACKET_LEN,
+    myheap,
+    supervisor::{self, Heartbeat},
+    MyHeapVec,
+};
+
+use super::framing::{Event, Framer, Framing, Oversize};
+
+pub static TCP_PACKET_LEN: usize = 64;
+
+/// Clients the server accepts at once, `TCP_CLIENTS`. It sizes the tables and task pools, so
+/// it is read at compile time; build.rs keeps it between 1 and 8.
+const TCP_CLIENTS: usize = match option_env!("TCP_CLIENTS") {
+    Some(n) => parse_count(n),
+    None => 4,
+};
+
+/// Slots for concurrent connections: the server's `TCP_CLIENTS`, and after them one for the
+/// outbound client when `TCP_REMOTE` is set.
+pub const TCP_MAX_CLIENTS: usize = TCP_CLIENTS + option_env!("TCP_REMOTE").is_some() as usize;
+
+const DEFAULT_PORT: u16 = 10001;
+
+/// Slack on top of `TcpConfig::idle_timeout` before a slot counts as stuck.
+const STUCK_SLACK: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct TcpConfig {
+    pub port: u16,
+    pub clients: usize,
+    /// `host:port` to dial out to, for sites where inbound connections are impossible.
+    pub remote: Option<&'static str>,
+    /// A client that neither sends nor receives anything for this long is disconnected.
+    pub idle_timeout: Duration,
+    pub framing: Framing,
+    pub oversize: Oversize,
+    /// Publish a partial frame once the client has been quiet for this long.
+    pub flush_after: Option<Duration>,
+}
+
+const fn parse_count(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut n = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            panic!("invalid TCP_CLIENTS");
+        }
+        n = n * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+    n
+}
+
+/// `TCP_FRAMING`: `newline`, `crlf`, `length`, or a delimiter byte as `delim:<n>`.
+fn framing() -> Framing {
+    match option_env!("TCP_FRAMING") {
+        Some("crlf") => Framing::CrLf,
+        Some("length") => Framing::LengthPrefix,
+        Some(other) => other
+            .strip_prefix("delim:")
+            .and_then(|byte| byte.parse().ok())
+            .map_or(Framing::Newline, Framing::Delimiter),
+        None => Framing::Newline,
+    }
+}
+
+/// `TCP_OVERSIZE`: `split`, `truncate` or `drop`.
+fn oversize() -> Oversize {
+    match option_env!("TCP_OVERSIZE") {
+        Some("truncate") => Oversize::Truncate,
+        Some("drop") => Oversize::Drop,
+        _ => Oversize::Split,
+    }
+}
+
+impl Default for TcpConfig {
+    /// `TCP_PORT`, `TCP_CLIENTS`, `TCP_FRAMING`, `TCP_OVERSIZE` and `TCP_FLUSH_MS` (0 or unset:
+    /// never flush early), when set.
+    fn default() -> Self {
+        Self {
+            port: option_env!("TCP_PORT")
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(DEFAULT_PORT),
+            clients: TCP_CLIENTS,
+            remote: option_env!("TCP_REMOTE"),
+            idle_timeout: Duration::from_secs(300),
+            framing: framing(),
+            oversize: oversize(),
+            flush_after: option_env!("TCP_FLUSH_MS")
+                .and_then(|ms| ms.parse().ok())
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+        }
+    }
+}
+
+struct Packet {
+    buf: MyHeapVec<u8>,
+    len: usize,
+}
+
+static WRITE: [Channel<CriticalSectionRawMutex, Packet, 2>; TCP_MAX_CLIENTS] =
+    [const { Channel::new() }; TCP_MAX_CLIENTS];
+pub(super) static CONNECTED: [AtomicBool; TCP_MAX_CLIENTS] =
+    [const { AtomicBool::new(false) }; TCP_MAX_CLIENTS];
+
+fn packet(buf: &[u8]) -> Option<Packet> {
+    let len = buf.len();
+    if len >= TCP_PACKET_LEN {
+        crate::log_error!("tcp packet too big: {}", len);
+        return None;
+    }
+    let Some(mut heap_buf) = crate::try_vec_in_myheap!(0u8; len) else {
+        myheap::count_dropped();
+        return None;
+    };
+    heap_buf.copy_from_slice(&buf[..len]);
+    Some(Packet { buf: heap_buf, len })
+}
+
+/// Sends to the client in `slot`, or to every connected client when `slot` is `None`.
+///
+/// Never waits: packets for a slot with no client, or whose queue is full, are dropped, so a
+/// slow or vanished client cannot hold up the MQTT receive path.
+pub fn tcp_send(slot: Option<usize>, buf: &[u8]) {
+    match slot {
+        Some(slot) => {
+            if !CONNECTED
+                .get(slot)
+                .is_some_and(|c| c.load(Ordering::Relaxed))
+            {
+                crate::log_warn!("tcp[{}] no client, dropping packet", slot);
+                return;
+            }
+            if let Some(packet) = packet(buf) {
+                if WRITE[slot].try_send(packet).is_err() {
+                    crate::log_warn!("tcp[{}] queue full, dropping packet", slot);
+                }
+            }
+        }
+        None => {
+            for (slot, write) in WRITE.iter().enumerate() {
+                if !CONNECTED[slot].load(Ordering::Relaxed) {
+                    continue;
+                }
+                let Some(packet) = packet(buf) else {
+                    return;
+                };
+                if write.try_send(packet).is_err() {
+                    crate::log_warn!("tcp[{}] queue full, dropping broadcast", slot);
+                }
+            }
+        }
+    }
+}
+
+/// Puts the task serving `slot` under supervision. A quiet client is only dropped after
+/// `idle_timeout`, so the task may go that long without checking in.
+pub(super) fn register(slot: usize, config: &TcpConfig) -> Heartbeat {
+    supervisor::register("tcp", Some(slot), config.idle_timeout + STUCK_SLACK)
+}
+
+/// Serves one client at a time on `slot`; spawn one task per slot to accept several clients on
+/// the same port. Data from any client is published on `/data`, and again on `/data/<n>` for
+/// the client in slot `n`.
+///
+/// Listens on the active uplink and drops the client when it switches.
+#[embassy_executor::task(pool_size = TCP_CLIENTS)]
+pub async fn tcp_task(slot: usize, config: TcpConfig) {
+    let mut rx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut tx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut uplink = link::receiver();
+    let heartbeat = register(slot, &config);
+
+    loop {
+        heartbeat.idle();
+        let stack = uplink.get().await.stack;
+        select(
+            accept_loop(
+                stack,
+                slot,
+                &config,
+                &mut rx_buffer[..],
+                &mut tx_buffer[..],
+                &heartbeat,
+            ),
+            uplink.changed(),
+        )
+        .await;
+        CONNECTED[slot].store(false, Ordering::Relaxed);
+    }
+}
+
+async fn accept_loop(
+    stack: Stack<'static>,
+    slot: usize,
+    config: &TcpConfig,
+    rx_buffer: &mut [u8],
+    tx_buffer: &mut [u8],
+    heartbeat: &Heartbeat,
+) -> ! {
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer[..], &mut tx_buffer[..]);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        heartbeat.idle();
+        let accepted = socket.accept(config.port).await;
+        heartbeat.beat();
+        if let Err(e) = accepted {
+            crate::log_info!("tcp[{}] accept error: {:?}", slot, e);
+            continue;
+        }
+
+        crate::log_info!(
+            "tcp[{}] accepted connection from {:?}",
+            slot,
+            socket.remote_endpoint()
+        );
+        serve(&mut socket, slot, config, heartbeat).await;
+        Timer::after_secs(1).await;
+    }
+}
+
+/// Bridges an established connection to `slot` until it ends, with connect/disconnect events
+/// on `/logs`. Shared by the server and client modes.
+pub(super) async fn serve(
+    socket: &mut TcpSocket<'_>,
+    slot: usize,
+    config: &TcpConfig,
+    heartbeat: &Heartbeat,
+) {
+    let remote = socket.remote_endpoint();
+    led::state(led::LedState::Ok).await;
+
+    // drop anything queued for the previous client of this slot
+    while WRITE[slot].try_receive().is_ok() {}
+    CONNECTED[slot].store(true, Ordering::Relaxed);
+    crate::log_info!("tcp[{}] connected: {:?}", slot, remote);
+
+    let reason = loop_s(socket, slot, config, heartbeat).await;
+
+    CONNECTED[slot].store(false, Ordering::Relaxed);
+    socket.close();
+    crate::log_info!("tcp[{}] disconnected: {:?} ({})", slot, remote, reason);
+}
+
+/// Pumps data both ways until the client goes away. Any socket error only ends this connection.
+async fn loop_s<'a>(
+    socket: &mut TcpSocket<'a>,
+    slot: usize,
+    config: &TcpConfig,
+    heartbeat: &Heartbeat,
+) -> &'static str {
+    let mut framer = Framer::new(config.framing, config.oversize, MQTT_PACKET_LEN - 1);
+
+    loop {
+        heartbeat.beat();
+        let pending = framer.flushable();
+        let read_future = socket.read_with(|buf| {
+            crate::log_debug!("{}", buf.escape_ascii());
+            framer.push(buf)
+        });
+        let flush_future = async {
+            match config.flush_after {
+                Some(after) if pending => Timer::after(after).await,
+                _ => core::future::pending().await,
+            }
+        };
+
+        let event = match select3(read_future, WRITE[slot].receive(), flush_future)
+            .with_timeout(config.idle_timeout)
+            .await
+        {
+            Err(_) => return "idle timeout",
+            Ok(select::Either3::First(Err(_))) => return "connection reset",
+            Ok(select::Either3::First(Ok(event))) => event,
+            Ok(select::Either3::Second(pk)) => {
+                if let Err(e) = socket.write_all(&pk.buf[..pk.len]).await {
+                    crate::log_warn!("tcp[{}] write {:?}", slot, e);
+                    return "write error";
+                }
+                None
+            }
+            Ok(select::Either3::Third(_)) => framer.flush(),
+        };
+
+        let Some(event) = event else {
+            continue; //receive part of the packet, wait for the rest
+        };
+        if event != Event::Frame {
+            crate::log_warn!("tcp[{}] oversize frame: {:?}", slot, event);
+        }
+        if event != Event::Dropped {
+            bus::send_or_drop(bus::Channel::Data, framer.frame());
+            bus::send_or_drop(bus::Channel::DataSlot(slot), framer.frame());
+            framer.clear();
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Written against the embedded-io-async and embedded-hal traits only, so `host-tests` can run
+// it on a simulated bus. Keep esp-hal and defmt out of this file.
+
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_hal::digital::OutputPin;
+use embedded_io_async::{Read, Write};
+
+const DEFAULT_PRE_TX_US: u64 = 100;
+const DEFAULT_POST_TX_US: u64 = 100;
+const DEFAULT_ECHO_TIMEOUT_MS: u64 = 20;
+
+/// Driver-enable timing for a half-duplex RS-485 transceiver.
+#[derive(Debug, Clone, Copy)]
+pub struct Rs485Config {
+    /// Time between raising DE and the first start bit.
+    pub pre_tx: Duration,
+    /// Time between the last stop bit leaving the shift register and dropping DE.
+    pub post_tx: Duration,
+    /// DE is driven high to transmit, or low for transceivers with an inverted enable.
+    pub de_active_high: bool,
+    /// The transceiver's receiver stays enabled while driving, so every byte we send is read back.
+    pub suppress_echo: bool,
+    /// How long to wait for our own echo before giving up on it.
+    pub echo_timeout: Duration,
+}
+
+impl Default for Rs485Config {
+    /// `RS485_PRE_TX_US`, `RS485_POST_TX_US`, `RS485_DE_ACTIVE` (`high` or `low`),
+    /// `RS485_ECHO` (`true` to suppress echo) and `RS485_ECHO_TIMEOUT_MS`, when set.
+    fn default() -> Self {
+        fn number(value: Option<&str>, default: u64) -> u64 {
+            value.and_then(|s| s.parse().ok()).unwrap_or(default)
+        }
+        Self {
+            pre_tx: Duration::from_micros(number(
+                option_env!("RS485_PRE_TX_US"),
+                DEFAULT_PRE_TX_US,
+            )),
+            post_tx: Duration::from_micros(number(
+                option_env!("RS485_POST_TX_US"),
+                DEFAULT_POST_TX_US,
+            )),
+            de_active_high: option_env!("RS485_DE_ACTIVE") != Some("low"),
+            suppress_echo: option_env!("RS485_ECHO") == Some("true"),
+            echo_timeout: Duration::from_millis(number(
+                option_env!("RS485_ECHO_TIMEOUT_MS"),
+                DEFAULT_ECHO_TIMEOUT_MS,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rs485Error<E> {
+    Write(E),
+    Read(E),
+    /// The byte read back at this offset differs from the one we sent: someone else was driving.
+    Collision(usize),
+    /// Only this many bytes of our own echo came back before `echo_timeout`.
+    EchoLost(usize),
+}
+
+/// Matches the bytes read back from the bus against the frame we just sent.
+pub struct EchoMatcher<'a> {
+    sent: &'a [u8],
+    matched: usize,
+}
+
+impl<'a> EchoMatcher<'a> {
+    pub fn new(sent: &'a [u8]) -> Self {
+        Self { sent, matched: 0 }
+    }
+
+    /// Consumes the echoed prefix of `rx` and returns how many bytes belonged to the echo.
+    /// Anything past that is traffic from another node.
+    pub fn feed<E>(&mut self, rx: &[u8]) -> Result<usize, Rs485Error<E>> {
+        let expected = &self.sent[self.matched..];
+        let n = expected.len().min(rx.len());
+        if let Some(i) = expected[..n].iter().zip(rx).position(|(a, b)| a != b) {
+            return Err(Rs485Error::Collision(self.matched + i));
+        }
+        self.matched += n;
+        Ok(n)
+    }
+
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+
+    pub fn done(&self) -> bool {
+        self.matched == self.sent.len()
+    }
+}
+
+fn drive<P: OutputPin>(de: &mut P, config: &Rs485Config, enabled: bool) {
+    // GPIO writes cannot fail on our targets
+    if enabled == config.de_active_high {
+        de.set_high().ok();
+    } else {
+        de.set_low().ok();
+    }
+}
+
+/// Drives `data` onto the line with DE held until the shift register is empty.
+///
+/// When echo suppression is on, the echo is consumed from the receiver and checked; bytes that
+/// arrived after it are moved to the front of `rx` and their count is returned.
+pub async fn transmit<U, P>(
+    uart: &mut U,
+    de: &mut P,
+    config: &Rs485Config,
+    data: &[u8],
+    rx: &mut [u8],
+) -> Result<usize, Rs485Error<U::Error>>
+where
+    U: Read + Write,
+    P: OutputPin,
+{
+    drive(de, config, true);
+    Timer::after(config.pre_tx).await;
+    let mut result = uart.write_all(data).await;
+    if result.is_ok() {
+        // write_all only fills the FIFO, flush waits for TX-done on the last stop bit
+        result = uart.flush().await;
+    }
+    Timer::after(config.post_tx).await;
+    drive(de, config, false);
+    result.map_err(Rs485Error::Write)?;
+
+    if !config.suppress_echo {
+        return Ok(0);
+    }
+
+    let mut echo = EchoMatcher::new(data);
+    let mut extra = 0;
+    while !echo.done() {
+        let len = match with_timeout(config.echo_timeout, uart.read(&mut rx[..])).await {
+            Ok(Ok(len)) => len,
+            Ok(Err(e)) => return Err(Rs485Error::Read(e)),
+            Err(_) => return Err(Rs485Error::EchoLost(echo.matched())),
+        };
+        let consumed = echo.feed(&rx[..len])?;
+        extra = len - consumed;
+        rx.copy_within(consumed..len, 0);
+    }
+    Ok(extra)
+}
@@ -0,0 +1,9 @@
+pub use half_duplex::Rs485Config;
+pub use rs485::Line;
+pub use task::{uart_send, uart_task, UART_PORTS};
+pub use transaction::uart_transaction_from_mqtt;
+
+mod half_duplex;
+mod rs485;
+mod task;
+mod transaction;
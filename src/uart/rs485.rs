@@ -0,0 +1,48 @@
+use embedded_io_async::Write;
+use esp_hal::{
+    gpio::Output,
+    uart::{IoError, Uart},
+    Async,
+};
+
+use super::half_duplex::{self, Rs485Config};
+
+pub type Rs485Error = half_duplex::Rs485Error<IoError>;
+
+/// Electrical interface of a port.
+pub enum Line {
+    /// Full-duplex, no direction control.
+    Rs232,
+    /// Half-duplex with a driver-enable pin.
+    Rs485(Output<'static>, Rs485Config),
+}
+
+impl defmt::Format for Rs485Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Rs485Error::Write(e) => defmt::write!(f, "Write({})", e),
+            Rs485Error::Read(e) => defmt::write!(f, "Read({})", e),
+            Rs485Error::Collision(at) => defmt::write!(f, "Collision({})", at),
+            Rs485Error::EchoLost(len) => defmt::write!(f, "EchoLost({})", len),
+        }
+    }
+}
+
+/// Drives `data` onto the line. On RS-485, DE is held until the shift register is empty.
+///
+/// When echo suppression is on, the echo is consumed from the receiver and checked; bytes that
+/// arrived after it are moved to the front of `rx` and their count is returned.
+pub(super) async fn transmit(
+    uart: &mut Uart<'static, Async>,
+    line: &mut Line,
+    data: &[u8],
+    rx: &mut [u8],
+) -> Result<usize, Rs485Error> {
+    match line {
+        Line::Rs232 => {
+            uart.write_all(data).await.map_err(Rs485Error::Write)?;
+            Ok(0)
+        }
+        Line::Rs485(de_pin, config) => half_duplex::transmit(uart, de_pin, config, data, rx).await,
+    }
+}
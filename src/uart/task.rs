@@ -0,0 +1,158 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_futures::select::select3;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Timer, WithTimeout};
+use esp_hal::{uart::Uart, Async};
+
+use crate::{bus, led, myheap, supervisor, udp, MyHeapVec};
+
+use super::{
+    rs485::{self, Line, Rs485Error},
+    transaction::{self, Status, Transaction, TRANSACTIONS},
+};
+
+pub static UART_PACKET_LEN: usize = 128;
+
+/// Number of serial ports served, `UART1` and `UART2`.
+pub const UART_PORTS: usize = 2;
+
+/// Number of frames that were corrupted by another node driving the bus, per port.
+pub static COLLISIONS: [AtomicU32; UART_PORTS] = [const { AtomicU32::new(0) }; UART_PORTS];
+
+/// How often an idle port checks in with the supervisor.
+const CHECK_IN: Duration = Duration::from_secs(10);
+/// Longest a write or transaction may take before the task counts as stuck.
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+struct Packet {
+    buf: MyHeapVec<u8>,
+    len: usize,
+}
+
+static WRITE: [Channel<CriticalSectionRawMutex, Packet, 2>; UART_PORTS] =
+    [const { Channel::new() }; UART_PORTS];
+
+pub async fn uart_send(port: usize, buf: &[u8]) {
+    let len = buf.len();
+    if len >= UART_PACKET_LEN {
+        crate::log_error!("uart{} packet too big: {}", port, len);
+        return;
+    }
+    let Some(mut heap_buf) = crate::try_vec_in_myheap!(0u8; len) else {
+        myheap::count_dropped();
+        return;
+    };
+    heap_buf.copy_from_slice(&buf[..len]);
+    WRITE[port].send(Packet { buf: heap_buf, len }).await;
+}
+
+/// Unsolicited data from port `n` is published on `/uart/<n>`.
+fn publish_rx(port: usize, data: &[u8]) {
+    crate::log_debug!("UART{} received: {:02x?}", port, data);
+    bus::send_or_drop(bus::Channel::Uart(port), data);
+    udp::udp_forward_uart(port, data);
+}
+
+#[embassy_executor::task(pool_size = UART_PORTS)]
+pub async fn uart_task(port: usize, mut uart: Uart<'static, Async>, mut line: Line) {
+    let mut buf = crate::vec_in_myheap!(0u8; 256);
+    let heartbeat = supervisor::register("uart", Some(port), STUCK_AFTER);
+    loop {
+        heartbeat.beat();
+        let event = select3(
+            WRITE[port].receive(),
+            TRANSACTIONS[port].receive(),
+            uart.read_async(&mut buf[..]),
+        )
+        .with_timeout(CHECK_IN)
+        .await;
+        let Ok(event) = event else {
+            continue;
+        };
+        match event {
+            embassy_futures::select::Either3::First(pkt) => {
+                let result =
+                    rs485::transmit(&mut uart, &mut line, &pkt.buf[..pkt.len], &mut buf[..]).await;
+                match result {
+                    Ok(0) => {}
+                    Ok(len) => publish_rx(port, &buf[..len]),
+                    Err(e) => report(port, e).await,
+                }
+            }
+            embassy_futures::select::Either3::Second(tx) => {
+                run_transaction(port, &mut uart, &mut line, &tx, &mut buf[..]).await;
+            }
+            embassy_futures::select::Either3::Third(Ok(len)) => {
+                publish_rx(port, &buf[..len]);
+            }
+            embassy_futures::select::Either3::Third(Err(e)) => {
+                crate::log_error!("uart{} read {:?}", port, e);
+                led::state(led::LedState::UartError).await;
+                Timer::after_secs(1).await;
+            }
+        }
+    }
+}
+
+/// Sends the request and collects bytes until the response is complete or the timeout expires.
+/// Anything received after the end of the response is forwarded as unsolicited data.
+async fn run_transaction(
+    port: usize,
+    uart: &mut Uart<'static, Async>,
+    line: &mut Line,
+    tx: &Transaction,
+    buf: &mut [u8],
+) {
+    let deadline = Instant::now() + tx.timeout;
+    let mut len = match rs485::transmit(uart, line, &tx.buf[..tx.len], buf).await {
+        Ok(len) => len,
+        Err(e) => {
+            report(port, e).await;
+            transaction::reply(port, &tx.id, Status::BusError, &[]);
+            return;
+        }
+    };
+
+    let (status, end) = loop {
+        if let Some(end) = tx.until.complete(&buf[..len]) {
+            break (Status::Ok, end);
+        }
+        if len == buf.len() {
+            break (Status::Overflow, len);
+        }
+        match uart
+            .read_async(&mut buf[len..])
+            .with_deadline(deadline)
+            .await
+        {
+            Ok(Ok(n)) => len += n,
+            Ok(Err(e)) => {
+                crate::log_error!("uart{} read {:?}", port, e);
+                break (Status::BusError, len);
+            }
+            Err(_) => break (Status::Timeout, len),
+        }
+    };
+
+    crate::log_info!(
+        "UART{} transaction {}: {:?} {:02x?}",
+        port,
+        tx.id.as_str(),
+        status,
+        &buf[..end]
+    );
+    transaction::reply(port, &tx.id, status, &buf[..end]);
+    if end < len {
+        publish_rx(port, &buf[end..len]);
+    }
+}
+
+async fn report(port: usize, e: Rs485Error) {
+    crate::log_error!("uart{} write {:?}", port, e);
+    if let Rs485Error::Collision(_) | Rs485Error::EchoLost(_) = e {
+        let count = COLLISIONS[port].fetch_add(1, Ordering::Relaxed) + 1;
+        crate::log_warn!("uart{} collision: {:?} (total {})", port, e, count);
+    }
+    led::state(led::LedState::UartError).await;
+}
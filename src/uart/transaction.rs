@@ -0,0 +1,177 @@
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::{Channel, TrySendError},
+};
+use embassy_time::Duration;
+use mountain_mqtt::client::EventHandlerError;
+use serde::{Deserialize, Serialize};
+
+use crate::{bus, myheap, MyHeapVec};
+
+use super::task::{UART_PACKET_LEN, UART_PORTS};
+
+const ID_LEN: usize = 32;
+/// Longest escape serde-json-core writes for one byte of a string, `\u00XX`.
+const MAX_ESCAPE_LEN: usize = 6;
+/// The rest of the reply: keys, quotes and the longest status.
+const REPLY_OVERHEAD: usize = 64;
+const DEFAULT_TIMEOUT_MS: u32 = 1000;
+const MAX_TIMEOUT_MS: u32 = 30_000;
+
+/// When a response is considered complete.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Until {
+    terminator: Option<u8>,
+    len: Option<usize>,
+}
+
+impl Until {
+    /// Returns the length of the response if `rx` holds a complete one.
+    pub(super) fn complete(&self, rx: &[u8]) -> Option<usize> {
+        if let Some(term) = self.terminator {
+            if let Some(i) = rx.iter().position(|&b| b == term) {
+                return Some(i + 1);
+            }
+        }
+        match self.len {
+            Some(len) if rx.len() >= len => Some(len),
+            _ => None,
+        }
+    }
+}
+
+pub(super) struct Transaction {
+    pub(super) id: heapless::String<ID_LEN>,
+    pub(super) buf: MyHeapVec<u8>,
+    pub(super) len: usize,
+    pub(super) until: Until,
+    pub(super) timeout: Duration,
+}
+
+/// Transactions are queued per port and run one at a time by that port's task, so they never
+/// interleave on a bus.
+pub(super) static TRANSACTIONS: [Channel<CriticalSectionRawMutex, Transaction, 4>; UART_PORTS] =
+    [const { Channel::new() }; UART_PORTS];
+
+#[derive(Deserialize)]
+struct Request<'a> {
+    id: &'a str,
+    #[serde(default)]
+    port: usize,
+    /// Hex encoded bytes to send.
+    data: &'a str,
+    #[serde(default)]
+    term: Option<u8>,
+    #[serde(default)]
+    len: Option<usize>,
+    #[serde(default)]
+    timeout_ms: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct Reply<'a> {
+    id: &'a str,
+    status: &'a str,
+    data: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub(super) enum Status {
+    Ok,
+    Timeout,
+    Overflow,
+    BusError,
+    /// The port already has a full queue of transactions.
+    Busy,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Timeout => "timeout",
+            Status::Overflow => "overflow",
+            Status::BusError => "bus_error",
+            Status::Busy => "busy",
+        }
+    }
+}
+
+/// Parses a `/rpc/uart` request:
+/// `{"id":"..","port":0,"data":"hex","term":13,"len":8,"timeout_ms":500}`.
+///
+/// `port` defaults to 0. Without `term` or `len` the response is whatever arrived when
+/// `timeout_ms` expires. A request for a port with a full queue is answered `busy` right away,
+/// rather than holding up the MQTT client until there is room.
+pub fn uart_transaction_from_mqtt(payload: &[u8]) -> Result<(), EventHandlerError> {
+    let (req, _) = serde_json_core::from_slice::<Request>(payload).map_err(|e| {
+        crate::log_error!("uart rpc {:?}", e);
+        EventHandlerError::InvalidApplicationMessage
+    })?;
+    let id = heapless::String::try_from(req.id)
+        .map_err(|_| EventHandlerError::InvalidApplicationMessage)?;
+    if req.port >= UART_PORTS {
+        return Err(EventHandlerError::InvalidApplicationMessage);
+    }
+    let hex = req.data.as_bytes();
+    if hex.len() % 2 != 0 || hex.len() / 2 >= UART_PACKET_LEN {
+        return Err(EventHandlerError::InvalidApplicationMessage);
+    }
+    let len = hex.len() / 2;
+    let Some(mut buf) = crate::try_vec_in_myheap!(0u8; len) else {
+        myheap::count_dropped();
+        return Ok(());
+    };
+    for (i, pair) in hex.chunks(2).enumerate() {
+        buf[i] = core::str::from_utf8(pair)
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok())
+            .ok_or(EventHandlerError::InvalidApplicationMessage)?;
+    }
+    let timeout_ms = req
+        .timeout_ms
+        .unwrap_or(DEFAULT_TIMEOUT_MS)
+        .min(MAX_TIMEOUT_MS);
+
+    let queued = TRANSACTIONS[req.port].try_send(Transaction {
+        id,
+        buf,
+        len,
+        until: Until {
+            terminator: req.term,
+            len: req.len,
+        },
+        timeout: Duration::from_millis(timeout_ms as u64),
+    });
+    if let Err(TrySendError::Full(tx)) = queued {
+        reply(req.port, &tx.id, Status::Busy, &[]);
+    }
+    Ok(())
+}
+
+/// Publishes the response to a transaction on `/uart/<port>/reply`, correlated by request id.
+pub(super) fn reply(port: usize, id: &str, status: Status, data: &[u8]) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    // every character of the id may need escaping
+    let payload_len = data.len() * 2 + ID_LEN * MAX_ESCAPE_LEN + REPLY_OVERHEAD;
+    let (Some(mut hex), Some(mut payload)) = (
+        crate::try_vec_in_myheap!(0u8; data.len() * 2),
+        crate::try_vec_in_myheap!(0u8; payload_len),
+    ) else {
+        myheap::count_dropped();
+        return;
+    };
+    for (i, b) in data.iter().enumerate() {
+        hex[2 * i] = HEX[(b >> 4) as usize];
+        hex[2 * i + 1] = HEX[(b & 0xf) as usize];
+    }
+    let reply = Reply {
+        id,
+        status: status.as_str(),
+        data: core::str::from_utf8(&hex).unwrap(),
+    };
+    match serde_json_core::to_slice(&reply, &mut payload[..]) {
+        Ok(len) => bus::send_or_drop(bus::Channel::UartReply(port), &payload[..len]),
+        Err(_) => crate::log_error!("uart{} reply to {:?} does not fit", port, id),
+    }
+}
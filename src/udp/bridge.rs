@@ -0,0 +1,174 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use embassy_futures::select::{select, select3, Either3};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::Duration;
+
+use crate::{
+    bus, link,
+    mqtt::MQTT_PACKET_LEN,
+    myheap,
+    supervisor::{self, Heartbeat},
+    uart, MyHeapVec,
+};
+
+pub static UDP_PACKET_LEN: usize = 512;
+const DEFAULT_PORT: u16 = 10002;
+/// Handing a datagram on takes a send; anything longer is stuck.
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct UdpConfig {
+    pub port: u16,
+    /// UART port whose traffic is also bridged to the last UDP peer.
+    pub uart: Option<usize>,
+}
+
+impl Default for UdpConfig {
+    /// `UDP_PORT` and `UDP_UART`, the UART port to bridge, when set.
+    fn default() -> Self {
+        Self {
+            port: option_env!("UDP_PORT")
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(DEFAULT_PORT),
+            uart: option_env!("UDP_UART")
+                .and_then(|port| port.parse().ok())
+                .filter(|&port| port < uart::UART_PORTS),
+        }
+    }
+}
+
+struct Packet {
+    buf: MyHeapVec<u8>,
+    len: usize,
+}
+
+static WRITE: Channel<CriticalSectionRawMutex, Packet, 2> = Channel::new();
+static UART_RX: Channel<CriticalSectionRawMutex, Packet, 4> = Channel::new();
+static BRIDGED_UART: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn packet(buf: &[u8]) -> Option<Packet> {
+    let len = buf.len();
+    if len >= UDP_PACKET_LEN {
+        crate::log_error!("udp packet too big: {}", len);
+        return None;
+    }
+    let Some(mut heap_buf) = crate::try_vec_in_myheap!(0u8; len) else {
+        myheap::count_dropped();
+        return None;
+    };
+    heap_buf.copy_from_slice(&buf[..len]);
+    Some(Packet { buf: heap_buf, len })
+}
+
+/// Sends a datagram to the last peer that sent us one. Never waits, since it is called from the
+/// MQTT client: a full queue drops the datagram.
+pub fn udp_send(buf: &[u8]) {
+    if let Some(packet) = packet(buf) {
+        if WRITE.try_send(packet).is_err() {
+            myheap::count_dropped();
+            crate::log_warn!("udp queue full, dropping packet");
+        }
+    }
+}
+
+/// Called with everything received on a UART port; forwarded only if that port is bridged.
+/// Never waits, so a missing UDP peer cannot stall the bus.
+pub fn udp_forward_uart(port: usize, buf: &[u8]) {
+    if BRIDGED_UART.load(Ordering::Relaxed) != port {
+        return;
+    }
+    if let Some(packet) = packet(buf) {
+        if UART_RX.try_send(packet).is_err() {
+            crate::log_warn!("udp queue full, dropping uart data");
+        }
+    }
+}
+
+/// Datagrams received on `UdpConfig::port` are published on `/udp` and, when a UART is bridged,
+/// written to it. `/rpc/udp` and the bridged UART's traffic go back to the last peer.
+///
+/// Binds on the active uplink and rebinds when it switches.
+#[embassy_executor::task]
+pub async fn udp_task(config: UdpConfig) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut tx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut buf = crate::vec_in_myheap!(0u8; MQTT_PACKET_LEN - 1);
+    let mut uplink = link::receiver();
+    let heartbeat = supervisor::register("udp", None, STUCK_AFTER);
+
+    if let Some(port) = config.uart {
+        BRIDGED_UART.store(port, Ordering::Relaxed);
+    }
+
+    loop {
+        heartbeat.idle();
+        let stack = uplink.get().await.stack;
+        let socket = UdpSocket::new(
+            stack,
+            &mut rx_meta,
+            &mut rx_buffer[..],
+            &mut tx_meta,
+            &mut tx_buffer[..],
+        );
+        select(
+            bridge(socket, &config, &mut buf[..], &heartbeat),
+            uplink.changed(),
+        )
+        .await;
+    }
+}
+
+async fn bridge(
+    mut socket: UdpSocket<'_>,
+    config: &UdpConfig,
+    buf: &mut [u8],
+    heartbeat: &Heartbeat,
+) {
+    if let Err(e) = socket.bind(config.port) {
+        crate::log_error!("udp bind {:?}", e);
+        core::future::pending::<()>().await;
+    }
+
+    let mut peer: Option<IpEndpoint> = None;
+    loop {
+        heartbeat.idle();
+        let event = select3(
+            socket.recv_from(&mut buf[..]),
+            WRITE.receive(),
+            UART_RX.receive(),
+        )
+        .await;
+        heartbeat.beat();
+        match event {
+            Either3::First(Ok((len, meta))) => {
+                if peer != Some(meta.endpoint) {
+                    crate::log_info!("udp peer: {:?}", meta.endpoint);
+                }
+                peer = Some(meta.endpoint);
+                bus::send_or_drop(bus::Channel::Udp, &buf[..len]);
+                if let Some(port) = config.uart {
+                    uart::uart_send(port, &buf[..len]).await;
+                }
+            }
+            Either3::First(Err(e)) => {
+                crate::log_warn!("udp recv {:?}", e);
+            }
+            Either3::Second(pk) | Either3::Third(pk) => {
+                let Some(peer) = peer else {
+                    crate::log_warn!("udp no peer, dropping packet");
+                    continue;
+                };
+                if let Err(e) = socket.send_to(&pk.buf[..pk.len], peer).await {
+                    crate::log_warn!("udp send {:?}", e);
+                }
+            }
+        }
+    }
+}
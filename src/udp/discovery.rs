@@ -0,0 +1,84 @@
+use alloc::{format, string::String};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    Stack,
+};
+use serde::Serialize;
+
+/// Well-known port installers broadcast probes to.
+pub const DISCOVERY_PORT: u16 = 30303;
+/// Payload of a probe; anything else is ignored.
+const PROBE: &[u8] = b"DISCOVER";
+const REPLY_SIZE: usize = 256;
+
+#[derive(Serialize)]
+struct Announce<'a> {
+    id: &'a str,
+    org: &'a str,
+    mac: &'a str,
+    ip: &'a str,
+    link: &'a str,
+    git: &'a str,
+}
+
+/// Answers broadcast probes on `DISCOVERY_PORT` with who we are, so boards can be found on a LAN
+/// without the broker. `link` names the interface `stack` runs on.
+#[embassy_executor::task(pool_size = 2)]
+pub async fn discovery_task(stack: Stack<'static>, link: &'static str) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0u8; 64];
+    let mut tx_buffer = [0u8; REPLY_SIZE];
+    let mut buf = [0u8; 64];
+    let mut reply = [0u8; REPLY_SIZE];
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(DISCOVERY_PORT) {
+        crate::log_error!("discovery bind {:?}", e);
+        return;
+    }
+
+    loop {
+        let (len, meta) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) => {
+                crate::log_warn!("discovery recv {:?}", e);
+                continue;
+            }
+        };
+        if buf[..len].trim_ascii() != PROBE {
+            continue;
+        }
+
+        let mac = format!("{}", stack.hardware_address());
+        let ip = match stack.config_v4() {
+            Some(config) => format!("{}", config.address.address()),
+            None => String::new(),
+        };
+        let len = serde_json_core::to_slice(
+            &Announce {
+                id: env!("ID"),
+                org: env!("ORG"),
+                mac: &mac,
+                ip: &ip,
+                link,
+                git: env!("GIT_HASH"),
+            },
+            &mut reply,
+        );
+        let Ok(len) = len else {
+            crate::log_error!("discovery reply too big");
+            continue;
+        };
+        crate::log_info!("discovery probe from {:?}", meta.endpoint);
+        if let Err(e) = socket.send_to(&reply[..len], meta.endpoint).await {
+            crate::log_warn!("discovery send {:?}", e);
+        }
+    }
+}
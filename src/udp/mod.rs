@@ -0,0 +1,5 @@
+pub use bridge::{udp_forward_uart, udp_send, udp_task, UdpConfig};
+pub use discovery::discovery_task;
+
+mod bridge;
+mod discovery;
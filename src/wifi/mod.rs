@@ -0,0 +1,154 @@
+use crate::{mk_static, netcfg::{self, IfaceConfig}};
+use alloc::string::ToString;
+use defmt::{println, Debug2Format};
+use embassy_executor::Spawner;
+use embassy_net::{Runner, Stack, StackResources};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal};
+use embassy_time::{Duration, WithTimeout};
+use esp_hal::{
+    peripherals::WIFI,
+    rng::Rng
+};
+use esp_radio::{
+    Controller, wifi::{
+        AccessPointConfig, AuthMethod, ClientConfig, Config, ModeConfig, WifiDevice,
+    }
+};
+use serde::Serialize;
+
+mod provision;
+mod station;
+
+const MAX_NETWORKS: usize = 16;
+const SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn wifi_stack(
+    radio: &'static Controller<'static>,
+    wifi: WIFI<'static>,
+    config: IfaceConfig,
+    spawner: Spawner,
+) -> Stack<'static> {
+    let rng = Rng::new();
+    let (mut wifi_controller, wifi_interface) = esp_radio::wifi::new(radio, wifi, Config::default()).unwrap();
+
+    // Init network stacks
+    let (sta_stack, sta_runner) = embassy_net::new(
+        wifi_interface.sta,
+        config.initial(),
+        mk_static!(
+            StackResources<{ netcfg::SOCKETS }>,
+            StackResources::<{ netcfg::SOCKETS }>::new()
+        ),
+        (rng.random() as u64) << 32 | rng.random() as u64,
+    );
+
+    // with no network to join, open the setup access point instead
+    let known = station::known_networks();
+    let mode_config = if !known.is_empty() {
+        // the connection task sets the network it picks
+        ModeConfig::Client(ClientConfig::default())
+    } else {
+        crate::log_info!(
+            "No wifi credentials, starting setup access point {}",
+            provision::AP_SSID
+        );
+        let (ap_stack, ap_runner) = embassy_net::new(
+            wifi_interface.ap,
+            provision::ap_config(),
+            mk_static!(StackResources<6>, StackResources::<6>::new()),
+            (rng.random() as u64) << 32 | rng.random() as u64,
+        );
+        spawner
+            .spawn(run_stack(ap_runner))
+            .inspect_err(|e| crate::log_error!("{:?}", e))
+            .unwrap();
+        spawner
+            .spawn(provision::dhcp_server_task(ap_stack))
+            .inspect_err(|e| crate::log_error!("{:?}", e))
+            .unwrap();
+        provision::spawn_setup(spawner, ap_stack);
+        // the station side stays enabled so the setup page can scan
+        ModeConfig::ApSta(
+            ClientConfig::default(),
+            AccessPointConfig::default()
+                .with_ssid(provision::AP_SSID.to_string())
+                .with_auth_method(AuthMethod::None),
+        )
+    };
+    // serial only, the configuration holds the wifi password
+    println!("Using wifi configuration: {:?}", Debug2Format(&mode_config));
+    wifi_controller.set_config(&mode_config).unwrap();
+
+    spawner
+        .spawn(run_stack(sta_runner))
+        .inspect_err(|e| crate::log_error!("{:?}", e))
+        .unwrap();
+    spawner
+        .spawn(station::connection(wifi_controller, known))
+        .inspect_err(|e| crate::log_error!("{:?}", e))
+        .unwrap();
+    spawner
+        .spawn(netcfg::dhcp_fallback_task(sta_stack, config))
+        .inspect_err(|e| crate::log_error!("{:?}", e))
+        .unwrap();
+
+    return sta_stack;
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn run_stack(mut runner: Runner<'static, WifiDevice<'static>>) {
+    runner.run().await
+}
+
+/////
+
+pub enum WifiCmd {
+    /// Scan and publish the result on `/wifi/scan`.
+    Scan,
+    /// Leave the network and stay off it until `Reconnect`.
+    Disconnect,
+    /// Drop the current network, if any, and join the best known one.
+    Reconnect,
+}
+
+pub static WIFI_CRL: Channel<CriticalSectionRawMutex, WifiCmd, 5> = Channel::new();
+
+/// An access point seen by the last scan.
+#[derive(Debug, Clone, Serialize)]
+pub struct Network {
+    pub ssid: heapless::String<32>,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    pub rssi: i8,
+    pub open: bool,
+}
+
+pub type Networks = heapless::Vec<Network, MAX_NETWORKS>;
+
+static SCAN_RESULT: Signal<CriticalSectionRawMutex, Networks> = Signal::new();
+
+/// Asks the connection task for a scan and returns what it found, strongest first. Empty if the
+/// scan failed or did not finish within `SCAN_TIMEOUT`.
+pub async fn scan() -> Networks {
+    SCAN_RESULT.reset();
+    WIFI_CRL.send(WifiCmd::Scan).await;
+    SCAN_RESULT
+        .wait()
+        .with_timeout(SCAN_TIMEOUT)
+        .await
+        .unwrap_or_default()
+}
+
+/// Handles `/rpc/wifi`: `scan`, `disconnect` or `reconnect`.
+pub async fn wifi_cmd_from_mqtt(payload: &[u8]) {
+    let cmd = match payload {
+        b"scan" => WifiCmd::Scan,
+        b"disconnect" => WifiCmd::Disconnect,
+        b"reconnect" => WifiCmd::Reconnect,
+        _ => {
+            crate::log_warn!("unknown wifi command {}", payload.escape_ascii());
+            return;
+        }
+    };
+    WIFI_CRL.send(cmd).await;
+}
@@ -0,0 +1,209 @@
+use core::net::Ipv4Addr;
+
+use edge_dhcp::{
+    server::{Server, ServerOptions},
+    Options, Packet,
+};
+use embassy_futures::select::select;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Ipv4Cidr, Stack, StaticConfigV4,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+use picoserve::{
+    extract::Form,
+    response::{File, Json},
+    routing::{get, post},
+    AppBuilder, AppRouter,
+};
+use serde::Deserialize;
+
+use crate::{mk_static, settings};
+
+/// Address of the device on its setup network.
+pub const AP_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+pub const AP_SSID: &str = concat!(env!("ID"), "-setup");
+const SETUP_URL: &str = "http://192.168.4.1/";
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const LEASES: usize = 8;
+const HTTP_PORT: u16 = 80;
+const WEB_TASKS: usize = 2;
+
+/// Raised once new settings are stored; the device then restarts into station mode.
+static PROVISIONED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn ap_config() -> embassy_net::Config {
+    embassy_net::Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(AP_IP, 24),
+        gateway: None,
+        dns_servers: heapless::Vec::new(),
+    })
+}
+
+/// Hands out addresses on the setup network and points clients at the setup page.
+#[embassy_executor::task]
+pub async fn dhcp_server_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = crate::vec_in_myheap!(0u8; 1536);
+    let mut tx_buffer = crate::vec_in_myheap!(0u8; 1536);
+    let mut buf = crate::vec_in_myheap!(0u8; 1024);
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer[..],
+        &mut tx_meta,
+        &mut tx_buffer[..],
+    );
+    if let Err(e) = socket.bind(DHCP_SERVER_PORT) {
+        crate::log_error!("dhcp server bind {:?}", e);
+        return;
+    }
+
+    let mut server = Server::<_, LEASES>::new(|| Instant::now().as_secs(), AP_IP);
+    let mut gw_buf = [Ipv4Addr::UNSPECIFIED];
+    let mut options = ServerOptions::new(AP_IP, Some(&mut gw_buf));
+    options.captive_url = Some(SETUP_URL);
+    let broadcast = IpEndpoint::new(Ipv4Addr::BROADCAST.into(), DHCP_CLIENT_PORT);
+
+    loop {
+        let len = match socket.recv_from(&mut buf[..]).await {
+            Ok((len, _)) => len,
+            Err(e) => {
+                crate::log_warn!("dhcp server recv {:?}", e);
+                continue;
+            }
+        };
+        let request = match Packet::decode(&buf[..len]) {
+            Ok(request) => request,
+            Err(e) => {
+                crate::log_warn!("dhcp server decode {:?}", e);
+                continue;
+            }
+        };
+        let mut opt_buf = Options::buf();
+        let Some(reply) = server.handle_request(&mut opt_buf, &options, &request) else {
+            continue;
+        };
+        let mut out = [0u8; 576];
+        match reply.encode(&mut out) {
+            // clients have no address yet, so every reply is broadcast
+            Ok(data) => {
+                if let Err(e) = socket.send_to(data, broadcast).await {
+                    crate::log_warn!("dhcp server send {:?}", e);
+                }
+            }
+            Err(e) => crate::log_warn!("dhcp server encode {:?}", e),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SetupForm {
+    ssid: heapless::String<32>,
+    password: heapless::String<64>,
+    broker: heapless::String<64>,
+    port: heapless::String<5>,
+}
+
+async fn connect(Form(form): Form<SetupForm>) -> &'static str {
+    if form.ssid.is_empty() {
+        return "SSID is required";
+    }
+    let port = form.port.parse().unwrap_or(0);
+    let result = settings::update(|s| {
+        s.wifi = Some(settings::WifiCredentials {
+            ssid: form.ssid,
+            password: form.password,
+        });
+        if !form.broker.is_empty() {
+            s.broker = Some(settings::Broker {
+                host: form.broker,
+                port: if port == 0 { 1883 } else { port },
+            });
+        }
+    });
+    match result {
+        Ok(()) => {
+            crate::log_info!("provisioned, restarting into station mode");
+            PROVISIONED.signal(());
+            "Saved. The device restarts and joins the network."
+        }
+        Err(e) => {
+            crate::log_error!("settings save {:?}", e);
+            "Could not save the settings"
+        }
+    }
+}
+
+struct SetupApp;
+
+impl AppBuilder for SetupApp {
+    type PathRouter = impl picoserve::routing::PathRouter;
+
+    fn build_app(self) -> picoserve::Router<Self::PathRouter> {
+        picoserve::Router::new()
+            .route(
+                "/",
+                get(|| async { File::html(include_str!("../captive.html")) }),
+            )
+            .route("/scan", get(|| async { Json(super::scan().await) }))
+            .route("/connect", post(connect))
+    }
+}
+
+/// Spawns the setup page on the access point stack.
+pub fn spawn_setup(spawner: embassy_executor::Spawner, stack: Stack<'static>) {
+    let app = mk_static!(AppRouter<SetupApp>, SetupApp.build_app());
+    let config = mk_static!(
+        picoserve::Config<Duration>,
+        picoserve::Config::new(picoserve::Timeouts {
+            start_read_request: Some(Duration::from_secs(5)),
+            persistent_start_read_request: Some(Duration::from_secs(1)),
+            read_request: Some(Duration::from_secs(1)),
+            write: Some(Duration::from_secs(1)),
+        })
+    );
+    for id in 0..WEB_TASKS {
+        spawner
+            .spawn(setup_task(id, stack, app, config))
+            .inspect_err(|e| crate::log_error!("{:?}", e))
+            .unwrap();
+    }
+}
+
+#[embassy_executor::task(pool_size = WEB_TASKS)]
+async fn setup_task(
+    id: usize,
+    stack: Stack<'static>,
+    app: &'static AppRouter<SetupApp>,
+    config: &'static picoserve::Config<Duration>,
+) {
+    let mut tcp_rx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut tcp_tx_buffer = crate::vec_in_myheap!(0u8; 1024);
+    let mut http_buffer = crate::vec_in_myheap!(0u8; 2048);
+
+    let serve = picoserve::listen_and_serve(
+        id,
+        app,
+        config,
+        stack,
+        HTTP_PORT,
+        &mut tcp_rx_buffer[..],
+        &mut tcp_tx_buffer[..],
+        &mut http_buffer[..],
+    );
+    // the first task restarts the device once the reply to `/connect` has gone out
+    let restart = async {
+        if id != 0 {
+            core::future::pending::<()>().await;
+        }
+        PROVISIONED.wait().await;
+        Timer::after_secs(2).await;
+        esp_hal::system::software_reset();
+    };
+    select(serve, restart).await;
+}
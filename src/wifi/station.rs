@@ -0,0 +1,341 @@
+use alloc::{format, string::ToString};
+use core::future::pending;
+
+use embassy_futures::select::{select3, Either3};
+use embassy_time::{Duration, Timer};
+use esp_radio::wifi::{
+    AuthMethod, ClientConfig, ModeConfig, ScanConfig, WifiController, WifiEvent,
+};
+use serde::Serialize;
+
+use crate::{
+    bus::{self, Channel},
+    settings,
+};
+
+use super::{Network, Networks, WifiCmd, SCAN_RESULT, WIFI_CRL};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How often the air is scanned for a better access point while connected.
+const SCAN_INTERVAL: Duration = Duration::from_secs(60);
+/// How much stronger another access point of the same priority must be before we roam to it.
+const ROAM_MARGIN: i8 = 10;
+const MAX_KNOWN: usize = 8;
+const STATE_PAYLOAD_SIZE: usize = 160;
+const SCAN_PAYLOAD_SIZE: usize = 1000;
+
+/// Priority of the network entered on the setup page.
+const STORED_PRIORITY: u8 = 200;
+/// Priority of the `SSID`/`WPWD` build variables.
+const BUILD_PRIORITY: u8 = 100;
+
+#[derive(Debug, Clone)]
+pub struct KnownNetwork {
+    pub ssid: heapless::String<32>,
+    pub password: heapless::String<64>,
+    pub priority: u8,
+}
+
+pub type KnownNetworks = heapless::Vec<KnownNetwork, MAX_KNOWN>;
+
+/// The networks we may join: the one stored from the setup page, `SSID`/`WPWD`, and the
+/// `WIFI_NETWORKS` build variable, a `;` separated list of `priority:ssid:password`.
+pub fn known_networks() -> KnownNetworks {
+    let mut known = KnownNetworks::new();
+    let mut add = |ssid: &str, password: &str, priority: u8| {
+        if ssid.is_empty() || known.iter().any(|k| k.ssid == ssid) {
+            return;
+        }
+        let (Ok(ssid), Ok(password)) = (ssid.try_into(), password.try_into()) else {
+            crate::log_error!("wifi network {} does not fit", ssid);
+            return;
+        };
+        known
+            .push(KnownNetwork {
+                ssid,
+                password,
+                priority,
+            })
+            .ok();
+    };
+
+    if let Some(stored) = settings::get().wifi {
+        add(&stored.ssid, &stored.password, STORED_PRIORITY);
+    }
+    add(
+        option_env!("SSID").unwrap_or(""),
+        option_env!("WPWD").unwrap_or(""),
+        BUILD_PRIORITY,
+    );
+    for entry in option_env!("WIFI_NETWORKS")
+        .unwrap_or("")
+        .split(';')
+        .filter(|e| !e.is_empty())
+    {
+        let mut parts = entry.splitn(3, ':');
+        match (
+            parts.next().and_then(|p| p.parse().ok()),
+            parts.next(),
+            parts.next(),
+        ) {
+            (Some(priority), Some(ssid), password) => add(ssid, password.unwrap_or(""), priority),
+            _ => crate::log_error!("invalid WIFI_NETWORKS entry {}", entry),
+        }
+    }
+    known
+}
+
+/// The best access point of a known network in `networks`: highest priority first, then
+/// strongest signal.
+fn pick<'a>(
+    known: &'a [KnownNetwork],
+    networks: &[Network],
+) -> Option<(&'a KnownNetwork, Network)> {
+    let mut best: Option<(&KnownNetwork, &Network)> = None;
+    for network in networks {
+        let Some(k) = known.iter().find(|k| k.ssid == network.ssid) else {
+            continue;
+        };
+        let better = match best {
+            None => true,
+            Some((b, bn)) => (k.priority, network.rssi) > (b.priority, bn.rssi),
+        };
+        if better {
+            best = Some((k, network));
+        }
+    }
+    best.map(|(k, n)| (k, n.clone()))
+}
+
+pub(super) async fn run_scan(controller: &mut WifiController<'static>) -> Networks {
+    let mut networks = Networks::new();
+    match controller
+        .scan_with_config_async(ScanConfig::default())
+        .await
+    {
+        Ok(mut found) => {
+            found.sort_by(|a, b| b.signal_strength.cmp(&a.signal_strength));
+            for ap in found {
+                let Ok(ssid) = heapless::String::try_from(ap.ssid.as_str()) else {
+                    continue;
+                };
+                let network = Network {
+                    ssid,
+                    bssid: ap.bssid,
+                    channel: ap.channel,
+                    rssi: ap.signal_strength,
+                    open: matches!(ap.auth_method, None | Some(AuthMethod::None)),
+                };
+                if networks.push(network).is_err() {
+                    break;
+                }
+            }
+        }
+        Err(e) => crate::log_info!("Wifi scan failed: {:?}", e),
+    }
+    networks
+}
+
+#[derive(Serialize)]
+struct StatePacket<'a> {
+    state: &'a str,
+    ssid: &'a str,
+    bssid: &'a str,
+    channel: u8,
+    rssi: i8,
+}
+
+fn report(state: &str, network: Option<&Network>) {
+    let bssid = network
+        .map(|n| {
+            let b = n.bssid;
+            format!(
+                "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                b[0], b[1], b[2], b[3], b[4], b[5]
+            )
+        })
+        .unwrap_or_default();
+    let packet = StatePacket {
+        state,
+        ssid: network.map(|n| n.ssid.as_str()).unwrap_or(""),
+        bssid: &bssid,
+        channel: network.map(|n| n.channel).unwrap_or(0),
+        rssi: network.map(|n| n.rssi).unwrap_or(0),
+    };
+    let mut payload = crate::vec_in_myheap!(0u8; STATE_PAYLOAD_SIZE);
+    if let Ok(len) = serde_json_core::to_slice(&packet, &mut payload[..]) {
+        bus::try_send(Channel::WifiState, &payload[..len]);
+    }
+}
+
+/// Publishes the scan result, leaving out the weakest networks if it does not fit in a packet.
+fn report_scan(networks: &Networks) {
+    let mut payload = crate::vec_in_myheap!(0u8; SCAN_PAYLOAD_SIZE);
+    for n in (0..=networks.len()).rev() {
+        if let Ok(len) = serde_json_core::to_slice(&networks[..n], &mut payload[..]) {
+            bus::try_send(Channel::WifiScan, &payload[..len]);
+            return;
+        }
+    }
+}
+
+/// Joins `network` using the credentials of `known`, pinned to its BSSID and channel.
+async fn join(
+    controller: &mut WifiController<'static>,
+    known: &KnownNetwork,
+    network: &Network,
+) -> bool {
+    crate::log_info!(
+        "About to connect to {} ({:?}, ch {}, {} dBm)",
+        known.ssid.as_str(),
+        network.bssid,
+        network.channel,
+        network.rssi
+    );
+    let config = ModeConfig::Client(
+        ClientConfig::default()
+            .with_ssid(known.ssid.to_string())
+            .with_password(known.password.to_string())
+            .with_bssid(network.bssid)
+            .with_channel(network.channel),
+    );
+    if let Err(e) = controller.set_config(&config) {
+        crate::log_info!("Failed to configure wifi: {:?}", e);
+        return false;
+    }
+    match controller.connect_async().await {
+        Ok(_) => {
+            crate::log_info!("STA connected");
+            true
+        }
+        Err(e) => {
+            crate::log_info!("Failed to connect to wifi: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Keeps the station joined to the best known network, roams when a better one shows up, and
+/// serves `WifiCmd`s.
+#[embassy_executor::task]
+pub(super) async fn connection(mut controller: WifiController<'static>, known: KnownNetworks) {
+    crate::log_info!("start connection task");
+    crate::log_info!("Device capabilities: {:?}", controller.capabilities());
+
+    crate::log_info!("Starting wifi");
+    controller.start_async().await.unwrap();
+    crate::log_info!("Wifi started!");
+
+    let mut current: Option<Network> = None;
+    // set by `Disconnect`, and while provisioning since there is nothing to join
+    let mut paused = known.is_empty();
+    let mut backoff = MIN_BACKOFF;
+    let mut attempt = !paused;
+
+    loop {
+        if attempt && current.is_none() && !paused {
+            let networks = run_scan(&mut controller).await;
+            if let Some((k, network)) = pick(&known, &networks) {
+                if join(&mut controller, k, &network).await {
+                    backoff = MIN_BACKOFF;
+                    report("connected", Some(&network));
+                    current = Some(network);
+                }
+            } else {
+                crate::log_info!("No known wifi network in range");
+            }
+        }
+        attempt = false;
+
+        let wait = match (&current, paused) {
+            (Some(_), _) => Some(SCAN_INTERVAL),
+            (None, false) => {
+                let wait = backoff;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                Some(wait)
+            }
+            (None, true) => None,
+        };
+        let timer = async {
+            match wait {
+                Some(wait) => Timer::after(wait).await,
+                None => pending::<()>().await,
+            }
+        };
+        let connected = current.is_some();
+        let disconnected = async {
+            if connected {
+                controller.wait_for_event(WifiEvent::StaDisconnected).await
+            } else {
+                pending::<()>().await
+            }
+        };
+
+        match select3(WIFI_CRL.receive(), disconnected, timer).await {
+            Either3::First(WifiCmd::Reconnect) => {
+                if current.take().is_some() {
+                    controller.disconnect_async().await.ok();
+                }
+                paused = known.is_empty();
+                backoff = MIN_BACKOFF;
+                attempt = true;
+            }
+            Either3::First(WifiCmd::Disconnect) => {
+                if current.take().is_some() {
+                    controller.disconnect_async().await.ok();
+                }
+                paused = true;
+                report("disconnected", None);
+            }
+            Either3::First(WifiCmd::Scan) => {
+                let networks = run_scan(&mut controller).await;
+                report_scan(&networks);
+                SCAN_RESULT.signal(networks);
+            }
+            Either3::Second(_) => {
+                crate::log_info!("STA disconnected");
+                current = None;
+                attempt = true;
+                report("disconnected", None);
+            }
+            Either3::Third(_) => {
+                attempt = true;
+                let Some(cur) = current.as_mut() else {
+                    continue;
+                };
+                let networks = run_scan(&mut controller).await;
+                if let Some(seen) = networks.iter().find(|n| n.bssid == cur.bssid) {
+                    cur.rssi = seen.rssi;
+                }
+                report("connected", Some(cur));
+
+                let priority = |ssid: &str| {
+                    known
+                        .iter()
+                        .find(|k| k.ssid == ssid)
+                        .map(|k| k.priority)
+                        .unwrap_or(0)
+                };
+                let Some((k, best)) = pick(&known, &networks) else {
+                    continue;
+                };
+                let roam = best.bssid != cur.bssid
+                    && (k.priority > priority(&cur.ssid)
+                        || (k.priority == priority(&cur.ssid)
+                            && best.rssi > cur.rssi.saturating_add(ROAM_MARGIN)));
+                if roam {
+                    crate::log_info!("Roaming to {:?} ({} dBm)", best.bssid, best.rssi);
+                    controller.disconnect_async().await.ok();
+                    current = None;
+                    if join(&mut controller, k, &best).await {
+                        report("connected", Some(&best));
+                        current = Some(best);
+                    } else {
+                        report("disconnected", None);
+                    }
+                }
+            }
+        }
+    }
+}